@@ -0,0 +1,231 @@
+//! keeps home-dir files displaced by `ff` in timestamped backup dirs
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use walkdir::WalkDir;
+
+use error::Error;
+use journal::*;
+use plan::*;
+use report::*;
+
+/// Returns current UTC time formatted as `YYYYmmdd-HHMMSS`
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // civil from days, see: http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Single backup stored in `<backups-dir>/<timestamp>`
+///
+/// Files keep their path relative to home-dir, so `/home/joe/.config/foo`
+/// is stored as `<backups-dir>/<timestamp>/.config/foo`
+#[derive(Debug, Clone)]
+pub struct Backup {
+    dir: PathBuf,
+    home_dir: PathBuf,
+}
+
+impl Backup {
+    /// Gives new backup (named by current time) stored in `backups_dir`
+    ///
+    /// Backup dir is not created until anything is backed up
+    pub fn new<P, H>(backups_dir: P, home_dir: H) -> Backup
+    where
+        P: AsRef<Path>,
+        H: AsRef<Path>,
+    {
+        let name = timestamp();
+        let mut dir = backups_dir.as_ref().join(&name);
+        let mut suffix = 1;
+        while dir.exists() {
+            dir = backups_dir.as_ref().join(format!("{}-{}", name, suffix));
+            suffix += 1;
+        }
+        Backup {
+            dir,
+            home_dir: home_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns dir where backed up files are stored
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns path where `user_file` is backed up
+    pub fn path_for(&self, user_file: &Path) -> Result<PathBuf, Error> {
+        let rel_path = user_file.strip_prefix(&self.home_dir).map_err(|_| {
+            Error::invalid_path(
+                user_file,
+                format!("Can't backup file outside of home dir {:?}", self.home_dir),
+            )
+        })?;
+        Ok(self.dir.join(rel_path))
+    }
+
+    /// Adds steps which move `user_file` to backup into `plan`
+    pub fn plan_backup(&self, user_file: &Path, plan: &mut Plan) -> Result<(), Error> {
+        let backup_file = self.path_for(user_file)?;
+        if let Some(parent) = backup_file.parent() {
+            if !parent.exists() {
+                plan.push(
+                    Operation::CreateDir(parent.to_path_buf()),
+                    "backup dir is missing",
+                );
+            }
+        }
+        plan.push(
+            Operation::Move {
+                from: user_file.to_path_buf(),
+                to: backup_file,
+            },
+            "home-dir file is backed up before it's replaced",
+        );
+        Ok(())
+    }
+}
+
+/// Returns names (timestamps) of backups stored in `backups_dir`, oldest first
+pub fn list_backups(backups_dir: &Path) -> Vec<String> {
+    let mut names = match fs::read_dir(backups_dir) {
+        Err(_) => return vec![],
+        Ok(v) => v
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect::<Vec<_>>(),
+    };
+    names.sort();
+    names
+}
+
+/// Plans moving files from backup `name` back to `home_dir`
+///
+/// Home-dir symlinks are replaced, regular files are never overwritten.
+/// Fails if any backed up file can't be read
+pub fn plan_restore_backup(
+    backups_dir: &Path,
+    name: &str,
+    home_dir: &Path,
+) -> Result<Vec<Plan>, Error> {
+    let backup_dir = backups_dir.join(name);
+    if name.is_empty() || !backup_dir.is_dir() {
+        return Err(Error::NotFound(format!(
+            "Can't find backup: {:?}",
+            backup_dir
+        )));
+    }
+    let mut plans = Vec::new();
+    for item_result in WalkDir::new(&backup_dir) {
+        let backup_file = match item_result {
+            Err(e) => {
+                let path = e.path().unwrap_or(&backup_dir).to_path_buf();
+                return Err(Error::io(format!("Can't read {:?}", path), e.into()));
+            }
+            Ok(v) => v,
+        };
+        if backup_file.file_type().is_dir() {
+            continue;
+        }
+        let rel_path = backup_file
+            .path()
+            .strip_prefix(&backup_dir)
+            .map_err(|_| Error::invalid_path(backup_file.path(), "Can't get relative path"))?;
+        let user_file = home_dir.join(rel_path);
+
+        let mut plan = Plan::for_file(&user_file);
+        match fs::symlink_metadata(&user_file) {
+            Ok(ref m) if m.file_type().is_symlink() => plan.push(
+                Operation::RemoveFile(user_file.clone()),
+                "home-dir symlink is replaced by backed up file",
+            ),
+            Ok(_) => {
+                plan.skip(format!("{:?} exists and is not a symlink", user_file));
+                plans.push(plan);
+                continue;
+            }
+            Err(_) => {
+                if let Some(parent) = user_file.parent() {
+                    if !parent.exists() {
+                        plan.push(
+                            Operation::CreateDir(parent.to_path_buf()),
+                            "home-dir lacks parent dir of backed up file",
+                        );
+                    }
+                }
+            }
+        }
+        plan.push(
+            Operation::Move {
+                from: backup_file.path().to_path_buf(),
+                to: user_file.clone(),
+            },
+            "backed up file is put back",
+        );
+        plan.on_done(format!(
+            "restored: {:?} (from: {:?})",
+            user_file,
+            backup_file.path()
+        ));
+        plans.push(plan);
+    }
+    Ok(plans)
+}
+
+/// Moves files from backup `name` back to `home_dir`
+/// (see: `ff::backup::plan_restore_backup` for details)
+///
+/// When `dry_run` is set operations are only planned
+pub fn restore_backup(
+    backups_dir: &Path,
+    name: &str,
+    home_dir: &Path,
+    dry_run: bool,
+) -> Result<Report, Error> {
+    let plans = plan_restore_backup(backups_dir, name, home_dir)?;
+    let mut report = Report::new("restore-backup");
+    if dry_run {
+        preview_plans(&plans, &mut report);
+        return Ok(report);
+    }
+    run_plans(&plans, true, &mut report);
+    remove_empty_dirs(&backups_dir.join(name));
+    Ok(report)
+}
+
+/// Removes `dir` and its subdirs as long as they are empty
+fn remove_empty_dirs(dir: &Path) {
+    let dirs = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<_>>();
+    for dir in dirs.iter().rev() {
+        // non-empty dirs are left on purpose
+        let _ = fs::remove_dir(dir);
+    }
+}
@@ -1,39 +1,178 @@
 //! defines CLI for ff
+use std::any::Any;
+use std::cell::Cell;
 use std::env;
+use std::fs;
+use std::mem;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::rc::Rc;
-use std;
 
-use fui::Fui;
+use fui::{Fui, Value};
 use fui::feeders::DirItems;
-use fui::fields::{Autocomplete, Multiselect};
+use fui::fields::{Autocomplete, Checkbox, FormField, Multiselect};
 use fui::form::FormView;
 use fui::utils::cwd;
 use fui::validators;
 
-use config::*;
-use core::*;
+use ff::backup::*;
+use ff::config::*;
+use ff::conflict::*;
+use ff::core::*;
+use ff::error::Error;
+use ff::git::*;
+use ff::history::*;
+use ff::journal::*;
+use ff::paths::*;
+use ff::report::*;
+use ff::status::*;
 
-fn get_fui(config: Config) -> Fui<'static, 'static> {
+/// Accepts paths of existing files, dirs and symlinks (also dangling ones)
+#[derive(Clone, Debug)]
+struct PathExists;
+
+impl validators::Validator for PathExists {
+    fn validate(&self, data: &str) -> Option<String> {
+        match fs::symlink_metadata(arg_path(data)) {
+            Ok(_) => None,
+            Err(_) => Some("Path doesn't exist".to_string()),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Accepts glob patterns (containing `*`, `?` or `[`) and paths accepted by `PathExists`
+///
+/// Empty value is accepted too (field isn't required)
+#[derive(Clone, Debug)]
+struct PathOrPattern;
+
+impl validators::Validator for PathOrPattern {
+    fn validate(&self, data: &str) -> Option<String> {
+        if data.is_empty() || data.contains(['*', '?', '['].as_ref()) {
+            return None;
+        }
+        PathExists.validate(data)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn get_fui(home: Home, config: Config, exit_code: &Rc<Cell<i32>>) -> Fui<'static, 'static> {
+    let backups = list_backups(&home.backups_dir());
+    let profiles = config.get_profiles().unwrap_or_default();
+    let home = Rc::new(home);
+    let home_init = Rc::clone(&home);
+    let home_add = Rc::clone(&home);
+    let home_remove = Rc::clone(&home);
+    let home_apply = Rc::clone(&home);
+    let home_status = Rc::clone(&home);
+    let home_pull = Rc::clone(&home);
+    let home_sync = Rc::clone(&home);
+    let home_bootstrap = Rc::clone(&home);
+    let home_restore_backup = Rc::clone(&home);
+    let home_undo = Rc::clone(&home);
+    let home_history = Rc::clone(&home);
     let config = Rc::new(config);
     let config_init = Rc::clone(&config);
     let config_add = Rc::clone(&config);
+    let config_remove = Rc::clone(&config);
     let config_apply = Rc::clone(&config);
+    let config_status = Rc::clone(&config);
+    let config_pull = Rc::clone(&config);
+    let config_sync = Rc::clone(&config);
+    let config_bootstrap = Rc::clone(&config);
+    let exit_init = Rc::clone(exit_code);
+    let exit_add = Rc::clone(exit_code);
+    let exit_remove = Rc::clone(exit_code);
+    let exit_apply = Rc::clone(exit_code);
+    let exit_status = Rc::clone(exit_code);
+    let exit_pull = Rc::clone(exit_code);
+    let exit_sync = Rc::clone(exit_code);
+    let exit_bootstrap = Rc::clone(exit_code);
+    let exit_restore_backup = Rc::clone(exit_code);
+    let exit_undo = Rc::clone(exit_code);
+    let exit_history = Rc::clone(exit_code);
     Fui::new()
         .action(
             "init",
             "select dir where dot-files will be stored",
-            FormView::new().field(
-                Autocomplete::new("dir-path", DirItems::dirs())
-                    .help("Path to dir where dot-files will be stored")
-                    .initial(cwd())
-                    .validator(validators::Required),
-            ),
+            FormView::new()
+                .field(
+                    Autocomplete::new("dir-path", DirItems::dirs())
+                        .help("Path to dir where dot-files will be stored")
+                        .initial(cwd())
+                        .validator(validators::Required),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
             move |v| {
-                action_init(
-                    v["dir-path"].as_str().expect("can't get dir-path"),
-                    &config_init,
-                );
+                let v = decode_args(v);
+                run_action("init", &v, &home_init, &exit_init, |_| {
+                    init(
+                        Path::new(v["dir-path"].as_str().expect("can't get dir-path")),
+                        &home_init,
+                        &config_init,
+                        v["dry-run"].as_bool().unwrap_or(false),
+                    )
+                });
+            },
+        )
+        .action(
+            "bootstrap",
+            "clones dot-files repository, makes it sync-dir and applies it",
+            FormView::new()
+                .field(
+                    Autocomplete::new("repo", DirItems::dirs())
+                        .help("URL (or path) of git repository with dot-files")
+                        .validator(validators::Required),
+                )
+                .field(
+                    Autocomplete::new("dest", DirItems::dirs())
+                        .help("Dir to clone repository into (default: home-dir/<repository name>)"),
+                )
+                .field(
+                    Autocomplete::new("sync-subdir", DirItems::dirs())
+                        .help("Path to sync-subdir where tracked files are stored")
+                        .initial("homedir"),
+                )
+                .field(
+                    Autocomplete::new("profile", profiles.clone())
+                        .help("Profile whose layers (sync-subdirs) are applied instead of sync-subdir"),
+                )
+                .field(
+                    Checkbox::new("keep-going")
+                        .help("Skip failing files instead of reverting all changes"),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
+            move |v| {
+                let v = decode_args(v);
+                run_action("bootstrap", &v, &home_bootstrap, &exit_bootstrap, |_| {
+                    let dest = v["dest"].as_str().unwrap_or("");
+                    let options = get_options(&v, &config_bootstrap)?;
+                    catch_interrupts();
+                    bootstrap(
+                        v["repo"].as_str().expect("can't get repo"),
+                        if dest.is_empty() { None } else { Some(Path::new(dest)) },
+                        v["sync-subdir"].as_str().unwrap_or(""),
+                        v["profile"].as_str().unwrap_or(""),
+                        &home_bootstrap,
+                        &config_bootstrap,
+                        &options,
+                    )
+                });
             },
         )
         .action(
@@ -42,65 +181,309 @@ fn get_fui(config: Config) -> Fui<'static, 'static> {
             FormView::new()
                 .field(
                     Multiselect::new("file-path", DirItems::new())
-                        .help("Path to file which should be tracked")
+                        .help("Path to file (or dir) which should be tracked")
                         .validator(validators::Required)
-                        .validator(validators::FileExists),
+                        .validator(PathExists),
                 )
                 .field(
                     Autocomplete::new("sync-subdir", DirItems::dirs())
                         .help("Path to dir where tracked file are stored")
                         .initial("homedir")
                         .validator(validators::Required),
-                ),
+                )
+                .field(
+                    Checkbox::new("relative")
+                        .help("Create symlinks relative to their location (config: relative-links)"),
+                )
+                .field(
+                    Checkbox::new("link-dir")
+                        .help("Symlink added dirs as a whole instead of each file they contain"),
+                )
+                .field(
+                    Checkbox::new("encrypt")
+                        .help("Store files encrypted (secrets), home-dir files are left as they are"),
+                )
+                .field(
+                    Checkbox::new("keep-going")
+                        .help("Skip failing files instead of reverting all changes"),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
             move |v| {
-                let file_paths = v.get("file-path")
-                    .unwrap()
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| x.as_str().unwrap())
-                    .collect::<Vec<&str>>();
-                if let Err(e) =
-                    action_add(&file_paths, v["sync-subdir"].as_str().unwrap(), &config_add)
-                {
-                    println!("{}", e);
-                }
+                let v = decode_args(v);
+                let file_paths = get_file_paths(&v);
+                run_action("add", &v, &home_add, &exit_add, |_| {
+                    action_add(
+                        &file_paths,
+                        v["sync-subdir"].as_str().unwrap(),
+                        &home_add,
+                        &config_add,
+                        &get_options(&v, &config_add)?,
+                    )
+                });
             },
         )
         .action(
             "remove",
             "removes home-dir files from sync-dir",
-            FormView::new().field(
-                Multiselect::new("file-path", DirItems::new())
-                    .help("Path to home-dir file which should be removed from sync-dir")
-                    .validator(validators::Required)
-                    .validator(validators::FileExists),
-            ),
+            FormView::new()
+                .field(
+                    Multiselect::new("file-path", DirItems::new())
+                        .help("Home-dir symlink (or dir), sync-dir file or glob pattern to remove")
+                        .validator(PathOrPattern),
+                )
+                .field(
+                    Checkbox::new("all")
+                        .help("Remove all files of sync-subdir (instead of file paths)"),
+                )
+                .field(
+                    Autocomplete::new("sync-subdir", DirItems::dirs())
+                        .help("Sync-subdir whose files are removed with --all")
+                        .initial("homedir"),
+                )
+                .field(
+                    Checkbox::new("copy-back")
+                        .help("Replace symlinks with copies, sync-dir files are left in place"),
+                )
+                .field(
+                    Checkbox::new("force")
+                        .help("Remove also symlinks which don't point inside sync-dir"),
+                )
+                .field(
+                    Checkbox::new("keep-going")
+                        .help("Skip failing files instead of reverting all changes"),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
             move |v| {
-                let file_paths = v.get("file-path")
-                    .unwrap()
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| x.as_str().unwrap())
-                    .collect::<Vec<&str>>();
-                action_remove(&file_paths)
+                let v = decode_args(v);
+                let file_paths = get_file_paths(&v);
+                run_action("remove", &v, &home_remove, &exit_remove, |_| {
+                    let all = v["all"].as_bool().unwrap_or(false);
+                    action_remove(
+                        &file_paths,
+                        all,
+                        v["sync-subdir"].as_str().unwrap_or(""),
+                        &home_remove,
+                        &config_remove,
+                        &get_options(&v, &config_remove)?,
+                    )
+                });
             },
         )
         .action(
             "apply",
             "replaces home-dir's files with aliases from sync-dir",
-            FormView::new().field(
-                Autocomplete::new("sync-subdir", DirItems::dirs())
-                    .help("Path to sync-subdir where tracked files are stored")
-                    .initial("homedir")
-                    .validator(validators::Required)
-                    .validator(validators::DirExists),
-            ),
+            FormView::new()
+                .field(
+                    Autocomplete::new("sync-subdir", DirItems::dirs())
+                        .help("Path to sync-subdir where tracked files are stored")
+                        .initial("homedir"),
+                )
+                .field(
+                    Autocomplete::new("profile", profiles.clone())
+                        .help("Profile whose layers (sync-subdirs) are applied instead of sync-subdir"),
+                )
+                .field(
+                    Autocomplete::new("on-conflict", CONFLICT_POLICIES.to_vec())
+                        .help("What to do with home-dir files which differ from sync-dir ones"),
+                )
+                .field(
+                    Checkbox::new("adopt")
+                        .help("Move existing home-dir files over sync-dir ones, then symlink them"),
+                )
+                .field(
+                    Checkbox::new("relative")
+                        .help("Create symlinks relative to their location (config: relative-links)"),
+                )
+                .field(
+                    Checkbox::new("keep-going")
+                        .help("Skip failing files instead of reverting all changes"),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
+            move |v| {
+                let v = decode_args(v);
+                run_action("apply", &v, &home_apply, &exit_apply, |_| {
+                    action_apply(
+                        v["sync-subdir"].as_str().unwrap_or(""),
+                        v["profile"].as_str().unwrap_or(""),
+                        &home_apply,
+                        &config_apply,
+                        &get_options(&v, &config_apply)?,
+                    )
+                });
+            },
+        )
+        .action(
+            "status",
+            "shows state of home-dir counterparts of sync-dir files",
+            FormView::new()
+                .field(
+                    Autocomplete::new("sync-subdir", DirItems::dirs())
+                        .help("Path to sync-subdir where tracked files are stored"),
+                )
+                .field(
+                    Autocomplete::new("profile", profiles.clone())
+                        .help("Profile whose layers (sync-subdirs) are checked instead of sync-subdir"),
+                )
+                .field(Checkbox::new("porcelain").help("Print output stable for scripts"))
+                .field(format_field()),
+            move |v| {
+                let v = decode_args(v);
+                let space_dir = v["sync-subdir"].as_str().unwrap_or("");
+                let profile = v["profile"].as_str().unwrap_or("");
+                let porcelain = v["porcelain"].as_bool().unwrap_or(false);
+                let result = get_format(&v).and_then(|format| {
+                    let layers = get_layers(space_dir, profile, &config_status)?;
+                    action_status(&layers, porcelain, format, &home_status, &config_status)
+                });
+                if let Err(e) = result {
+                    fail(&e, &exit_status);
+                }
+            },
+        )
+        .action(
+            "pull",
+            "copies home-dir copies modified since apply back to sync-dir",
+            FormView::new()
+                .field(
+                    Autocomplete::new("sync-subdir", DirItems::dirs())
+                        .help("Path to sync-subdir where tracked files are stored")
+                        .initial("homedir"),
+                )
+                .field(
+                    Autocomplete::new("profile", profiles.clone())
+                        .help("Profile whose layers (sync-subdirs) are pulled instead of sync-subdir"),
+                )
+                .field(
+                    Checkbox::new("keep-going")
+                        .help("Skip failing files instead of reverting all changes"),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
+            move |v| {
+                let v = decode_args(v);
+                run_action("pull", &v, &home_pull, &exit_pull, |_| {
+                    action_pull(
+                        v["sync-subdir"].as_str().unwrap_or(""),
+                        v["profile"].as_str().unwrap_or(""),
+                        &home_pull,
+                        &config_pull,
+                        &get_options(&v, &config_pull)?,
+                    )
+                });
+            },
+        )
+        .action(
+            "sync",
+            "pulls sync-dir git repository, applies it and pushes it back",
+            FormView::new()
+                .field(
+                    Autocomplete::new("sync-subdir", DirItems::dirs())
+                        .help("Path to sync-subdir where tracked files are stored")
+                        .initial("homedir"),
+                )
+                .field(
+                    Autocomplete::new("profile", profiles)
+                        .help("Profile whose layers (sync-subdirs) are applied instead of sync-subdir"),
+                )
+                .field(
+                    Autocomplete::new("on-conflict", CONFLICT_POLICIES.to_vec())
+                        .help("What to do with home-dir files which differ from sync-dir ones"),
+                )
+                .field(
+                    Checkbox::new("keep-going")
+                        .help("Skip failing files instead of reverting all changes"),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
             move |v| {
-                let space_dir = v["sync-subdir"].as_str().unwrap();
-                if let Err(e) = action_apply(space_dir, &config_apply) {
-                    println!("{}", e);
+                let v = decode_args(v);
+                run_action("sync", &v, &home_sync, &exit_sync, |_| {
+                    action_sync(
+                        v["sync-subdir"].as_str().unwrap_or(""),
+                        v["profile"].as_str().unwrap_or(""),
+                        &home_sync,
+                        &config_sync,
+                        &get_options(&v, &config_sync)?,
+                    )
+                });
+            },
+        )
+        .action(
+            "restore-backup",
+            "puts back home-dir files backed up by apply",
+            FormView::new()
+                .field(
+                    Autocomplete::new("timestamp", backups)
+                        .help("Name (timestamp) of backup to restore")
+                        .validator(validators::Required),
+                )
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print planned operations without changing anything"),
+                )
+                .field(format_field()),
+            move |v| {
+                let v = decode_args(v);
+                let timestamp = v["timestamp"].as_str().unwrap();
+                let dry_run = v["dry-run"].as_bool().unwrap_or(false);
+                run_action(
+                    "restore-backup",
+                    &v,
+                    &home_restore_backup,
+                    &exit_restore_backup,
+                    |_| {
+                        restore_backup(
+                            &home_restore_backup.backups_dir(),
+                            timestamp,
+                            home_restore_backup.path(),
+                            dry_run,
+                        )
+                    },
+                );
+            },
+        )
+        .action(
+            "undo",
+            "reverts changes made by the last action (see: history)",
+            FormView::new()
+                .field(
+                    Checkbox::new("dry-run")
+                        .help("Print operations to revert without changing anything"),
+                )
+                .field(format_field()),
+            move |v| {
+                let v = decode_args(v);
+                let dry_run = v["dry-run"].as_bool().unwrap_or(false);
+                run_action("undo", &v, &home_undo, &exit_undo, |_| {
+                    History::new(home_undo.history_dir()).undo_last(dry_run)
+                });
+            },
+        )
+        .action(
+            "history",
+            "lists past actions which can be undone",
+            FormView::new(),
+            move |_| {
+                if let Err(e) = action_history(&home_history) {
+                    fail(&e, &exit_history);
                 }
             },
         )
@@ -110,136 +493,310 @@ fn get_fui(config: Config) -> Fui<'static, 'static> {
         .author(crate_authors!())
 }
 
-/// Returns path to config file
-pub fn get_config_file_path() -> PathBuf {
-    let mut conf_file = env::home_dir().expect("Can't find home dir");
-    conf_file.push(".ff");
-    conf_file.push("config.ini");
-    conf_file
+/// Returns field choosing output format of actions
+fn format_field() -> impl FormField {
+    Autocomplete::new("format", FORMATS.to_vec())
+        .help("Output format: text (default) or json (one record per file)")
+}
+
+/// Returns output format chosen in `v`
+fn get_format(v: &Value) -> Result<Format, Error> {
+    v["format"].as_str().unwrap_or("").parse()
+}
+
+/// Returns file paths chosen in `v`
+fn get_file_paths(v: &Value) -> Vec<PathBuf> {
+    v.get("file-path")
+        .and_then(|x| x.as_array())
+        .map(|paths| {
+            paths
+                .iter()
+                .filter_map(|x| x.as_str())
+                .filter(|x| !x.is_empty())
+                .map(arg_path)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns path given as command line argument `text` (see: `run_encoded`)
+fn arg_path(text: &str) -> PathBuf {
+    if env::var_os(ENCODED_ARGS).is_some() {
+        decode_path(text)
+    } else {
+        PathBuf::from(text)
+    }
 }
 
-fn home_dir_contained<T: AsRef<Path>>(dir: T) -> Result<bool, String> {
-    let home_dir = std::env::home_dir().ok_or("Can't find home dir")?;
-    Ok(dir.as_ref().starts_with(&home_dir))
+/// Returns `v` with its texts decoded (see: `run_encoded`), file paths are left
+/// encoded because they may not be UTF-8 (see: `get_file_paths`)
+fn decode_args(v: Value) -> Value {
+    if env::var_os(ENCODED_ARGS).is_none() {
+        return v;
+    }
+    match v {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| match value {
+                    Value::String(ref text) if name != "file-path" => {
+                        let text = decode_path(text).to_string_lossy().into_owned();
+                        (name, Value::String(text))
+                    }
+                    value => (name, value),
+                })
+                .collect(),
+        ),
+        v => v,
+    }
+}
+
+/// Returns options chosen in `v`, those not chosen are taken from `config`
+fn get_options(v: &Value, config: &Config) -> Result<Options, Error> {
+    let mut options = Options::from_config(config)?;
+    options.dry_run = v["dry-run"].as_bool().unwrap_or(false);
+    options.keep_going = v["keep-going"].as_bool().unwrap_or(false);
+    if v["relative"].as_bool().unwrap_or(false) {
+        options.relative_links = true;
+    }
+    options.link_dirs = v["link-dir"].as_bool().unwrap_or(false);
+    options.encrypt = v["encrypt"].as_bool().unwrap_or(false);
+    options.force = v["force"].as_bool().unwrap_or(false);
+    options.copy_back = v["copy-back"].as_bool().unwrap_or(false);
+    options.adopt = v["adopt"].as_bool().unwrap_or(false);
+    match v["on-conflict"].as_str() {
+        Some(policy) if !policy.is_empty() => options.on_conflict = policy.parse()?,
+        _ => {}
+    }
+    Ok(options)
 }
 
-fn action_init(sync_dir: &str, config: &Config) {
-    let _sync_dir = match sync_dir.len() {
-        0 => match std::env::current_dir() {
-            Err(e) => {
-                println!("Can't get home dir ({})", e);
-                return;
+/// Returns `action` with its arguments (`v`) formatted like command line
+fn describe(action: &str, v: &Value) -> String {
+    let mut args = vec![action.to_owned()];
+    if let Some(fields) = v.as_object() {
+        for (name, value) in fields {
+            match *value {
+                Value::Bool(true) => args.push(format!("--{}", name)),
+                Value::String(ref text) if !text.is_empty() => {
+                    args.push(format!("--{} {}", name, text))
+                }
+                Value::Array(ref items) => {
+                    let items = items
+                        .iter()
+                        .filter_map(|x| x.as_str())
+                        .map(|x| arg_path(x).to_string_lossy().into_owned())
+                        .collect::<Vec<_>>();
+                    args.push(format!("--{} {}", name, items.join(" ")));
+                }
+                _ => {}
             }
-            Ok(v) => v,
-        },
-        _ => Path::new(sync_dir).to_path_buf(),
-    };
-    let _sync_dir = match std::fs::canonicalize(&_sync_dir) {
-        Err(e) => {
-            println!("Can't canonicalize: {:?} ({})", &_sync_dir, e);
-            return;
         }
+    }
+    args.join(" ")
+}
+
+/// Prints `error` and sets `exit_code` to the one of `error`
+fn fail(error: &Error, exit_code: &Cell<i32>) {
+    println!("{}", error);
+    exit_code.set(error.exit_code());
+}
+
+/// Runs `action` (in output format chosen in `v`), prints its report
+/// and stores its changes in `home` history
+///
+/// `exit_code` is set when anything fails
+fn run_action<F>(action: &str, v: &Value, home: &Home, exit_code: &Cell<i32>, run: F)
+where
+    F: FnOnce(Format) -> Result<Report, Error>,
+{
+    let format = match get_format(v) {
+        Err(e) => return fail(&e, exit_code),
         Ok(v) => v,
     };
-    let is_home_dir = match home_dir_contained(&_sync_dir) {
+    let mut report = match run(format) {
         Err(e) => {
-            println!(
-                "Can't validate if {:?} is descendat of home dir ({})",
-                &_sync_dir, e
-            );
+            print_error(action, &e, format);
+            exit_code.set(e.exit_code());
             return;
         }
         Ok(v) => v,
     };
-    if !is_home_dir {
-        println!("Sync dir should be descendant of home dir");
-        return;
+    report.print(format);
+    if let Some(e) = report.error() {
+        exit_code.set(e.exit_code());
     }
+    let done = mem::take(&mut report.done);
+    let history = History::new(home.history_dir());
+    if let Err(e) = history.record(&describe(action, v), done) {
+        eprintln!("Can't record changes in history ({})", e);
+        exit_code.set(e.exit_code());
+    }
+}
 
-    // TODO1: tmp conversion until code after action_ is moved to PathBuf
-    let _sync_dir = match _sync_dir.as_path().to_str() {
-        None => {
-            println!("Can't convert {:?} to str", _sync_dir);
-            return;
-        }
-        Some(v) => v,
-    };
-    if let Err(e) = init(_sync_dir, config) {
-        println!("{}", e);
+fn action_add(
+    file_paths: &[PathBuf],
+    space_dir: &str,
+    home: &Home,
+    config: &Config,
+    options: &Options,
+) -> Result<Report, Error> {
+    let sync_dir = SyncDir::from_config(config)?.subdir(space_dir);
+    catch_interrupts();
+    add(file_paths, home, &sync_dir, options)
+}
+
+fn action_remove(
+    file_paths: &[PathBuf],
+    all: bool,
+    space_dir: &str,
+    home: &Home,
+    config: &Config,
+    options: &Options,
+) -> Result<Report, Error> {
+    let sync_dir = SyncDir::from_config(config)?;
+    let mut files = file_paths.to_vec();
+    if all {
+        files.push(sync_dir.subdir(space_dir).path().to_path_buf());
+    }
+    if files.is_empty() {
+        return Err(Error::InvalidArgument(
+            "Give files to remove (--file-path) or --all".to_owned(),
+        ));
     }
+    catch_interrupts();
+    remove(&files, home, &sync_dir, options)
 }
 
-fn action_add(file_paths: &[&str], space_dir: &str, config: &Config) -> Result<(), String> {
-    let home_dir = env::home_dir().ok_or("Can't get home dir")?;
-    let home_dir = home_dir.to_str().ok_or("Can't convert home dir to str")?;
-    let sync_dir = with_space_dir(space_dir, config)?;
-    let sync_dir = sync_dir.to_str().ok_or("Can't convert home dir to str")?;
-    add_files(file_paths, home_dir, sync_dir);
+/// Returns layers of `profile` or `space_dir` of sync-dir (if `profile` is empty)
+fn get_layers(space_dir: &str, profile: &str, config: &Config) -> Result<Vec<SyncDir>, Error> {
+    let sync_dir = SyncDir::from_config(config)?;
+    if profile.is_empty() {
+        return Ok(vec![sync_dir.subdir(space_dir)]);
+    }
+    sync_dir.layers(config, profile)
+}
+
+fn action_apply(
+    space_dir: &str,
+    profile: &str,
+    home: &Home,
+    config: &Config,
+    options: &Options,
+) -> Result<Report, Error> {
+    let layers = get_layers(space_dir, profile, config)?;
+    catch_interrupts();
+    apply_layers(home, &layers, options)
+}
+
+fn action_pull(
+    space_dir: &str,
+    profile: &str,
+    home: &Home,
+    config: &Config,
+    options: &Options,
+) -> Result<Report, Error> {
+    let layers = get_layers(space_dir, profile, config)?;
+    catch_interrupts();
+    pull(home, &layers, options)
+}
+
+fn action_sync(
+    space_dir: &str,
+    profile: &str,
+    home: &Home,
+    config: &Config,
+    options: &Options,
+) -> Result<Report, Error> {
+    let sync_dir = SyncDir::from_config(config)?;
+    let layers = get_layers(space_dir, profile, config)?;
+    catch_interrupts();
+    sync(sync_dir.path(), home, &layers, options)
+}
+
+fn action_status(
+    layers: &[SyncDir],
+    porcelain: bool,
+    format: Format,
+    home: &Home,
+    config: &Config,
+) -> Result<(), Error> {
+    let options = Options::from_config(config)?;
+    let statuses = status(home, layers, &options)?;
+    if format == Format::Json {
+        print_json(&statuses);
+    } else if porcelain {
+        print_porcelain(&statuses);
+    } else {
+        print_table(&statuses);
+    }
+    print_mode_drift(&mode_drift(home, layers, &options)?, porcelain, format);
+    let git = Git::new(SyncDir::from_config(config)?.path());
+    if git.is_repo() {
+        print_uncommitted(&git.uncommitted()?, porcelain, format);
+    }
     Ok(())
 }
 
-fn with_space_dir(space_dir: &str, config: &Config) -> Result<PathBuf, String> {
-    let sync_dir = config
-        .get("sync-dir")
-        .map_err(|e| format!("Can't read sync-dir from {} ({})", config.get_path(), e))?
-        .ok_or_else(|| {
-            format!(
-                "Can't find 'sync-dir' value in config file: {}\n\
-                 Did you run: 'ff init' on your sync-dir?",
-                config.get_path()
-            )
-        })?;
-    let mut sync_dir = PathBuf::from(sync_dir);
-    if space_dir != "" {
-        sync_dir = sync_dir.join(space_dir);
-    }
-    Ok(sync_dir)
-}
-
-fn action_remove(file_paths: &[&str]) {
-    remove_files(file_paths);
-}
-
-fn action_apply(space_dir: &str, config: &Config) -> Result<(), String> {
-    let sync_dir = with_space_dir(space_dir, config)?;
-    let sync_dir = sync_dir
-        .to_str()
-        .ok_or("Can't convert sync-dir with space to str")?;
-    let home_dir = std::env::home_dir().ok_or("Can't get home dir")?;
-    let home_dir = home_dir
-        .to_str()
-        .ok_or_else(|| "Can't convert home dir to str".to_owned())?;
-    let to_ignore = config.get("ignore-when-apply").map_err(|e| {
-        format!(
-            "Can't get ignore-when-apply from {} ({})",
-            config.get_path(),
-            e
-        )
-    })?;
-    let to_ignore = match to_ignore.as_ref() {
-        None => vec![],
-        Some(v) => v.split(',').collect(),
-    };
-    apply(sync_dir, sync_dir, home_dir, &to_ignore)
+fn action_history(home: &Home) -> Result<(), Error> {
+    for entry in History::new(home.history_dir()).entries()? {
+        println!("{}  {}", entry.name, entry.args);
+    }
+    Ok(())
+}
+
+/// Set (for ff itself) when command line arguments are encoded (see: `run_encoded`)
+const ENCODED_ARGS: &str = "FF_ENCODED_ARGS";
+
+/// Runs ff again with arguments encoded by `ff::config::encode_path` when any of them
+/// isn't UTF-8 (which command line parser rejects) and returns its exit code
+///
+/// Returns `None` when arguments can be used as they are
+fn run_encoded() -> Option<i32> {
+    let args = env::args_os().skip(1).collect::<Vec<_>>();
+    if env::var_os(ENCODED_ARGS).is_some() || args.iter().all(|x| x.to_str().is_some()) {
+        return None;
+    }
+    let status = env::current_exe().and_then(|exe| {
+        Command::new(exe)
+            .args(args.iter().map(|x| encode_path(Path::new(x))))
+            .env(ENCODED_ARGS, "1")
+            .status()
+    });
+    Some(match status {
+        Err(e) => {
+            let e = Error::io("Can't run ff with encoded arguments", e);
+            println!("{}", e);
+            e.exit_code()
+        }
+        Ok(status) => status.code().unwrap_or(1),
+    })
 }
 
 /// Defines and initialize command line dispatcher which run suitable actions
-pub fn run_cli() {
-    let conf_path = get_config_file_path();
-    let conf_path = match conf_path.to_str() {
-        None => {
-            println!("Can't convert config path");
-            return;
+///
+/// Returns process exit code (see: `ff::error::Error::exit_code`)
+pub fn run_cli() -> i32 {
+    if let Some(exit_code) = run_encoded() {
+        return exit_code;
+    }
+    let home = match Home::current() {
+        Err(e) => {
+            println!("{}", e);
+            return e.exit_code();
         }
-        Some(v) => v,
+        Ok(v) => v,
     };
-    let config = match Config::new(conf_path) {
+    let conf_path = home.config_file();
+    let config = match Config::new(&conf_path) {
         Err(e) => {
-            println!("Can't initialize config file {}: ({})", conf_path, e);
-            return;
+            println!("Can't initialize config file {}: ({})", conf_path.display(), e);
+            return e.exit_code();
         }
         Ok(v) => v,
     };
 
-    get_fui(config).run();
+    let exit_code = Rc::new(Cell::new(0));
+    get_fui(home, config, &exit_code).run();
+    exit_code.get()
 }
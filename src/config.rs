@@ -1,12 +1,27 @@
 //! relates to config management for `ff` like (`Cofnig` manager)
 use std;
-use std::path::Path;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 use ini::Ini;
 
+use conflict::ConflictPolicy;
+use error::Error;
+
+/// Prefix of config sections defining profiles
+const PROFILE_PREFIX: &str = "profile.";
+/// Config section defining template variables
+const VARS_SECTION: &str = "vars";
+/// Config key telling how paths are stored (see: `Config::set_path_value`)
+const PATH_ENCODING: &str = "path-encoding";
+/// `PATH_ENCODING` of paths with some bytes stored as `%XX`
+const PERCENT_ENCODING: &str = "percent";
+
 /// Config manager storing data in files
 #[derive(Debug)]
 pub struct Config {
-    path: String,
+    path: PathBuf,
 }
 
 #[allow(missing_docs)]
@@ -14,42 +29,42 @@ impl Config {
     /// Gives instance of `Config` stored at `path`
     ///
     /// Config file is created (including necessery dirs.) if not exists
-    pub fn new<T>(path: T) -> Result<Config, String>
+    pub fn new<T>(path: T) -> Result<Config, Error>
     where
-        T: AsRef<str>,
+        T: AsRef<Path>,
     {
         let config = Config {
-            path: path.as_ref().to_owned(),
+            path: path.as_ref().to_path_buf(),
         };
         config.create_config_dir()?;
         Ok(config)
     }
 
     /// Returns path to file where config is stored
-    pub fn get_path(&self) -> &str {
+    pub fn get_path(&self) -> &Path {
         &self.path
     }
 
     /// Creates config file (including missing dirs.) from `self.path`
-    fn create_config_dir(&self) -> Result<(), String> {
-        let path = Path::new(&self.path);
+    fn create_config_dir(&self) -> Result<(), Error> {
+        let path = self.path.as_path();
         let parent_dir = path.parent()
-            .ok_or_else(|| format!("Can't get parent for {}", self.path))?;
+            .ok_or_else(|| Error::invalid_path(path, "Can't get parent"))?;
         if !parent_dir.exists() {
-            std::fs::create_dir_all(&parent_dir)
-                .map_err(|e| format!("Can't create config file dir: {:?} ({})", &parent_dir, e))?;
+            std::fs::create_dir_all(&parent_dir).map_err(|e| {
+                Error::io(format!("Can't create config file dir: {:?}", &parent_dir), e)
+            })?;
         }
-        if !Path::new(&self.path).exists() {
+        if !path.exists() {
             std::fs::File::create(path)
-                .map_err(|e| format!("Can't create config file {:?} ({:?})", path, e))?;
+                .map_err(|e| Error::io(format!("Can't create config file {:?}", path), e))?;
         }
         Ok(())
     }
 
     /// Returns config value for `key` wrapped with `Option` and `Result`
-    pub fn get(&self, key: &str) -> Result<Option<String>, String> {
-        let conf = Ini::load_from_file(self.path.as_str())
-            .map_err(|e| format!("Can't load config file: {} ({})", self.path, e))?;
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let conf = self.load()?;
 
         let v = conf.section(None::<String>)
             .and_then(|s| s.get(key).cloned())
@@ -58,16 +73,226 @@ impl Config {
     }
 
     /// Sets and save `value` under `key` in file
-    pub fn set(&self, key: &str, value: &str) -> Result<(), String> {
-        let mut conf = Ini::load_from_file(&self.path)
-            .map_err(|e| format!("Can't load config: {} ({})", &self.path, e))?;
+    pub fn set(&self, key: &str, value: &str) -> Result<(), Error> {
+        let mut conf = self.load()?;
         conf.with_section(None::<String>).set(key, value);
-        if let Err(e) = conf.write_to_file(self.path.as_str()) {
-            return Err(format!(
-                "Can't save {}={} to {} ({})",
-                &key, &value, self.path, e
+        let written = fs::File::create(&self.path).and_then(|mut file| conf.write_to(&mut file));
+        if let Err(e) = written {
+            return Err(Error::io(
+                format!("Can't save {}={} to {:?}", &key, &value, self.path),
+                e,
             ));
         }
         Ok(())
     }
+
+    /// Returns path stored under `key` (see: `set_path_value`)
+    ///
+    /// Configs saved before paths were encoded (without `PATH_ENCODING` set) store them as they are
+    pub fn get_path_value(&self, key: &str) -> Result<Option<PathBuf>, Error> {
+        let encoded = self.get(PATH_ENCODING)?.as_deref() == Some(PERCENT_ENCODING);
+        Ok(self.get(key)?.map(|v| if encoded { decode_path(&v) } else { PathBuf::from(v) }))
+    }
+
+    /// Sets and save `path` under `key` in file
+    ///
+    /// Bytes which aren't UTF-8 (and `%`) are stored as `%XX`, so any path is kept as it is
+    /// (`PATH_ENCODING` marks such config)
+    pub fn set_path_value(&self, key: &str, path: &Path) -> Result<(), Error> {
+        if self.get(PATH_ENCODING)?.as_deref() != Some(PERCENT_ENCODING) {
+            if let Some(old_path) = self.get_path_value("sync-dir")? {
+                // re-saved, so it's read the same way as before
+                self.set("sync-dir", &encode_path(&old_path))?;
+            }
+            self.set(PATH_ENCODING, PERCENT_ENCODING)?;
+        }
+        self.set(key, &encode_path(path))
+    }
+
+    /// Returns `true` if value of `key` is `true` (or `yes`, `1`)
+    pub fn get_bool(&self, key: &str) -> Result<bool, Error> {
+        Ok(match self.get(key)? {
+            Some(v) => ["true", "yes", "1"].contains(&v.trim().to_lowercase().as_str()),
+            None => false,
+        })
+    }
+
+    /// Returns `sync-dir` value, fails if it's not set (see: `ff::core::init`)
+    pub fn get_sync_dir(&self) -> Result<PathBuf, Error> {
+        self.get_path_value("sync-dir")?.ok_or_else(|| Error::NotInitialized {
+            path: self.path.clone(),
+        })
+    }
+
+    /// Returns paths (relative to sync-dir) which are skipped by apply (`ignore-when-apply`)
+    pub fn get_ignored(&self) -> Result<Vec<String>, Error> {
+        Ok(match self.get("ignore-when-apply")? {
+            None => vec![],
+            Some(v) => v.split(',').map(|x| x.to_owned()).collect(),
+        })
+    }
+
+    /// Returns patterns of sync-dir files copied to home-dir instead of being symlinked (`copy`)
+    pub fn get_copied(&self) -> Result<Vec<String>, Error> {
+        Ok(match self.get("copy")? {
+            None => vec![],
+            Some(v) => v.split(',')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+        })
+    }
+
+    /// Returns `on-conflict` policy, the default one if it's not set
+    pub fn get_on_conflict(&self) -> Result<ConflictPolicy, Error> {
+        match self.get("on-conflict")? {
+            None => Ok(ConflictPolicy::default()),
+            Some(v) => v.parse(),
+        }
+    }
+
+    /// Returns names of profiles defined in config (sections like `[profile.laptop]`)
+    pub fn get_profiles(&self) -> Result<Vec<String>, Error> {
+        let conf = self.load()?;
+        Ok(conf.sections()
+            .filter_map(|s| s.as_ref())
+            .filter(|s| s.starts_with(PROFILE_PREFIX))
+            .map(|s| s[PROFILE_PREFIX.len()..].to_owned())
+            .collect())
+    }
+
+    /// Returns layers (sync-subdirs) of `profile`, e.g. `layers=common,laptop` of `[profile.laptop]`
+    pub fn get_layers(&self, profile: &str) -> Result<Vec<String>, Error> {
+        let conf = self.load()?;
+        let section = format!("{}{}", PROFILE_PREFIX, profile);
+        let properties = conf.section(Some(section.as_str()))
+            .ok_or_else(|| Error::NotFound(format!("Can't find profile: {:?}", profile)))?;
+        let layers = properties.get("layers").ok_or_else(|| Error::Config {
+            path: self.path.clone(),
+            reason: format!("[{}] lacks 'layers' value", section),
+        })?;
+        Ok(layers
+            .split(',')
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .collect())
+    }
+
+    /// Returns template variables defined in `[vars]` section (see: `ff::template`)
+    pub fn get_vars(&self) -> Result<Vec<(String, String)>, Error> {
+        let conf = self.load()?;
+        Ok(match conf.section(Some(VARS_SECTION)) {
+            None => vec![],
+            Some(properties) => properties
+                .iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect(),
+        })
+    }
+
+    /// Loads config file
+    fn load(&self) -> Result<Ini, Error> {
+        let text = fs::read_to_string(&self.path).map_err(|e| Error::Config {
+            path: self.path.clone(),
+            reason: e.to_string(),
+        })?;
+        Ini::load_from_str(&text).map_err(|e| Error::Config {
+            path: self.path.clone(),
+            reason: e.to_string(),
+        })
+    }
+}
+
+/// Returns `path` as text, bytes which aren't UTF-8 and `%` are replaced with `%XX`
+pub fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    let mut bytes = path.as_os_str().as_bytes();
+    while !bytes.is_empty() {
+        let valid = match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        };
+        encoded.push_str(&valid.replace('%', "%25"));
+        bytes = &bytes[valid.len()..];
+        if let Some((&invalid, rest)) = bytes.split_first() {
+            encoded.push_str(&format!("%{:02X}", invalid));
+            bytes = rest;
+        }
+    }
+    encoded
+}
+
+/// Reverts `encode_path`
+pub fn decode_path(text: &str) -> PathBuf {
+    let mut decoded = Vec::with_capacity(text.len());
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn get_layers_reads_profile_section() {
+        let dir = TempDir::new("ff").unwrap();
+        let path = dir.path().join("config.ini");
+        File::create(&path)
+            .unwrap()
+            .write_all(b"sync-dir=/home/joe/dot-files\n[profile.laptop]\nlayers=common, laptop\n")
+            .unwrap();
+        let config = Config::new(&path).unwrap();
+
+        assert_eq!(config.get_profiles().unwrap(), vec!["laptop"]);
+        assert_eq!(config.get_layers("laptop").unwrap(), vec!["common", "laptop"]);
+        assert_eq!(config.get_layers("desktop").unwrap_err().exit_code(), 3);
+    }
+
+    #[test]
+    fn paths_are_stored_losslessly() {
+        let dir = TempDir::new("ff").unwrap();
+        let config = Config::new(dir.path().join("config.ini")).unwrap();
+        let path = Path::new(OsStr::from_bytes(
+            b"/home/joe/100%\\xff \xff\xfe\xc3 zaz\xc3\xb3\xc5\x82\xc4\x87",
+        ));
+
+        config.set_path_value("sync-dir", path).unwrap();
+
+        assert_eq!(config.get_sync_dir().unwrap(), path);
+    }
+
+    #[test]
+    fn paths_of_config_saved_before_encoding_are_read_as_they_are() {
+        let dir = TempDir::new("ff").unwrap();
+        File::create(dir.path().join("config.ini"))
+            .unwrap()
+            .write_all(b"sync-dir=/home/joe/100%41\n")
+            .unwrap();
+        let config = Config::new(dir.path().join("config.ini")).unwrap();
+
+        assert_eq!(config.get_sync_dir().unwrap(), Path::new("/home/joe/100%41"));
+        config.set_path_value("other-dir", Path::new("/tmp")).unwrap();
+        assert_eq!(config.get_sync_dir().unwrap(), Path::new("/home/joe/100%41"));
+    }
 }
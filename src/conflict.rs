@@ -0,0 +1,153 @@
+//! decides what happens to home-dir files which differ from their sync-dir counterparts
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use cursive::Cursive;
+use cursive::traits::Boxable;
+use cursive::views::{Dialog, TextView};
+
+use error::Error;
+
+/// What to do with home-dir file which differs from sync-dir file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConflictPolicy {
+    /// Leaves home-dir file untouched
+    Skip,
+    /// Removes home-dir file
+    Overwrite,
+    /// Moves home-dir file to backup
+    #[default]
+    Backup,
+    /// Shows diff and asks user for each file
+    Interactive,
+}
+
+/// Policy names accepted by `ConflictPolicy::from_str`
+pub const CONFLICT_POLICIES: [&str; 4] = ["backup", "skip", "overwrite", "interactive"];
+
+impl FromStr for ConflictPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ConflictPolicy, Error> {
+        match s {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "backup" => Ok(ConflictPolicy::Backup),
+            "interactive" => Ok(ConflictPolicy::Interactive),
+            _ => Err(Error::InvalidArgument(format!(
+                "Unknown conflict policy: {:?} (expected one of: {})",
+                s,
+                CONFLICT_POLICIES.join(", ")
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Backup => "backup",
+            ConflictPolicy::Interactive => "interactive",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Returns `true` if both paths can be read and have the same contents
+pub fn same_contents(path_a: &Path, path_b: &Path) -> bool {
+    match (fs::read(path_a), fs::read(path_b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Returns line-based diff turning `old` into `new`
+///
+/// Lines are prefixed with `-` (only in `old`), `+` (only in `new`) or ` ` (in both)
+pub fn diff(old: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    // lcs[i][j] is length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            out.push_str(&format!("  {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("- {}\n", old[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+ {}\n", new[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+/// Returns diff between `user_file` and `sync_file` readable by humans
+fn diff_files(user_file: &Path, sync_file: &Path) -> String {
+    match (
+        fs::read_to_string(user_file),
+        fs::read_to_string(sync_file),
+    ) {
+        (Ok(old), Ok(new)) => diff(&old, &new),
+        _ => format!("{:?} differs from {:?}", user_file, sync_file),
+    }
+}
+
+/// Shows diff of `user_file` and `sync_file` in TUI and asks what to do
+///
+/// Returns `ConflictPolicy::Skip` when user cancels
+pub fn ask(user_file: &Path, sync_file: &Path) -> ConflictPolicy {
+    let choice = Rc::new(Cell::new(ConflictPolicy::Skip));
+    let mut c = Cursive::new();
+    let mut dialog = Dialog::around(TextView::new(diff_files(user_file, sync_file)))
+        .title(format!("{} (- home-dir, + sync-dir)", user_file.display()));
+    for &policy in &[
+        ConflictPolicy::Backup,
+        ConflictPolicy::Overwrite,
+        ConflictPolicy::Skip,
+    ] {
+        let choice = Rc::clone(&choice);
+        dialog = dialog.button(policy.to_string(), move |c| {
+            choice.set(policy);
+            c.quit();
+        });
+    }
+    c.add_layer(dialog.full_screen());
+    c.run();
+    choice.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_marks_changed_lines() {
+        let old = "a\nb\nc\n";
+        let new = "a\nB\nc\nd\n";
+
+        let result = diff(old, new);
+
+        assert_eq!(result, "  a\n- b\n+ B\n  c\n+ d\n");
+    }
+}
@@ -0,0 +1,154 @@
+//! home-dir copies of sync-dir files (used instead of symlinks) and hashes of their contents
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::GitignoreBuilder;
+use sha2::{Digest, Sha256};
+
+use error::Error;
+use history::{escape, unescape};
+use paths::SyncDir;
+
+/// Returns hash (SHA-256 as hex) of `contents`
+pub fn hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// Returns hash of contents of file at `path`
+pub fn file_hash(path: &Path) -> Result<String, Error> {
+    fs::read(path)
+        .map(|contents| hash(&contents))
+        .map_err(|e| Error::io(format!("Can't read {:?}", path), e))
+}
+
+/// Returns `true` if `sync_file` is copied to home-dir instead of being symlinked
+///
+/// `patterns` use `.gitignore` syntax and are relative to `sync_dir` (see: `Options::copy`)
+pub fn is_copied(sync_file: &Path, sync_dir: &SyncDir, patterns: &[String]) -> Result<bool, Error> {
+    if patterns.is_empty() {
+        return Ok(false);
+    }
+    let mut builder = GitignoreBuilder::new(sync_dir.path());
+    for pattern in patterns {
+        builder.add_line(None, pattern).map_err(|e| {
+            Error::InvalidArgument(format!("Invalid copy pattern: {:?} ({})", pattern, e))
+        })?;
+    }
+    let matcher = builder
+        .build()
+        .map_err(|e| Error::InvalidArgument(format!("Invalid copy patterns ({})", e)))?;
+    Ok(matcher
+        .matched_path_or_any_parents(sync_file, false)
+        .is_ignore())
+}
+
+/// Hashes of home-dir copies recorded when their contents were the same as sync-dir ones
+///
+/// Comparing them with current contents tells which side was modified since
+/// (see: `ff::status::copy_state`)
+#[derive(Debug)]
+pub struct Copies {
+    path: PathBuf,
+    hashes: BTreeMap<PathBuf, String>,
+}
+
+impl Copies {
+    /// Loads hashes stored in file at `path` (nothing is recorded if it doesn't exist)
+    ///
+    /// Each line is: `<hash>\t<home-dir file>`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Copies, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut hashes = BTreeMap::new();
+        if path.exists() {
+            let content =
+                fs::read(&path).map_err(|e| Error::io(format!("Can't read {:?}", path), e))?;
+            for line in content.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                let fields = line.splitn(2, |&b| b == b'\t').collect::<Vec<_>>();
+                if fields.len() != 2 {
+                    return Err(Error::Corrupted {
+                        path,
+                        reason: format!("line: {:?}", String::from_utf8_lossy(line)),
+                    });
+                }
+                hashes.insert(
+                    PathBuf::from(OsString::from_vec(unescape(fields[1]))),
+                    String::from_utf8_lossy(fields[0]).into_owned(),
+                );
+            }
+        }
+        Ok(Copies { path, hashes })
+    }
+
+    /// Returns hash recorded for `user_file`
+    pub fn get(&self, user_file: &Path) -> Option<&str> {
+        self.hashes.get(user_file).map(|h| h.as_str())
+    }
+
+    /// Records `hash` of `user_file`, returns `true` if it wasn't recorded already
+    pub fn set<T: Into<String>>(&mut self, user_file: &Path, hash: T) -> bool {
+        let hash = hash.into();
+        if self.get(user_file) == Some(hash.as_str()) {
+            return false;
+        }
+        self.hashes.insert(user_file.to_path_buf(), hash);
+        true
+    }
+
+    /// Saves recorded hashes (see: `load`)
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| Error::io(format!("Can't create dir: {:?}", dir), e))?;
+        }
+        let mut content = Vec::new();
+        for (user_file, hash) in &self.hashes {
+            content.extend_from_slice(hash.as_bytes());
+            content.push(b'\t');
+            content.extend(escape(user_file.as_os_str().as_bytes()));
+            content.push(b'\n');
+        }
+        // hashes of secrets are kept private (see: `ff::secret::secret_hash`)
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)
+            .and_then(|mut file| {
+                file.set_permissions(fs::Permissions::from_mode(0o600))?;
+                file.write_all(&content)
+            })
+            .map_err(|e| Error::io(format!("Can't write {:?}", self.path), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn copies_are_matched_by_patterns_and_hashes_are_saved() {
+        let dir = TempDir::new("ff").unwrap();
+        let sync_dir = SyncDir::new("/home/joe/dot-files");
+        let patterns = vec![".ssh/config".to_owned(), "*.desktop".to_owned()];
+
+        assert!(is_copied(Path::new("/home/joe/dot-files/.ssh/config"), &sync_dir, &patterns).unwrap());
+        assert!(is_copied(Path::new("/home/joe/dot-files/a/b.desktop"), &sync_dir, &patterns).unwrap());
+        assert!(!is_copied(Path::new("/home/joe/dot-files/.bashrc"), &sync_dir, &patterns).unwrap());
+
+        let mut copies = Copies::load(dir.path().join("copies")).unwrap();
+        assert!(copies.set(Path::new("/home/joe/tab\there"), hash(b"x")));
+        assert!(!copies.set(Path::new("/home/joe/tab\there"), hash(b"x")));
+        copies.save().unwrap();
+        let copies = Copies::load(dir.path().join("copies")).unwrap();
+        assert_eq!(copies.get(Path::new("/home/joe/tab\there")), Some(hash(b"x").as_str()));
+        assert_eq!(fs::metadata(dir.path().join("copies")).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -1,176 +1,1394 @@
 //! delivers functionlity of ff
 use std;
 use std::*;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use std::path::PathBuf;
-use std::os::unix::fs as unix_fs;
 use std::result::Result;
 
-use walkdir::{DirEntry, WalkDir};
+use ignore::gitignore::GitignoreBuilder;
+use walkdir::{DirEntry, WalkDir, WalkDirIterator};
 
+use backup::*;
 use config::*;
+use conflict::*;
+use copies::*;
+use error::Error;
+use ffignore::*;
+use git::commit_report;
+use journal::*;
+use modes::*;
+use paths::*;
+use plan::*;
+use report::*;
+use secret::*;
+use status::{file_state, FileState};
+use template::*;
 
-/// Replaces `old_value` with `new_value` in `text`
+/// Returns `path` moved from `old_base` dir to `new_base` dir
 ///
-/// ```
-/// let text = "/home/joe/.bashrc";
-/// let old_value = "/home/joe";
-/// let new_value = "/home/joe/dot-files";
-/// let result = swap_path_bases(&text, &old_value, &new_value)
-/// assert_eq!(result, "/home/joe/dot-files/.bashrc");
+/// ```ignore
+/// let result = swap_path_bases(
+///     Path::new("/home/joe/.bashrc"),
+///     Path::new("/home/joe"),
+///     Path::new("/home/joe/dot-files"),
+/// );
+/// assert_eq!(result, Some(PathBuf::from("/home/joe/dot-files/.bashrc")));
 /// ```
 ///
-fn swap_path_bases(text: &str, old_value: &str, new_value: &str) -> String {
-    // notes:
-    // asRef fix it? https://doc.rust-lang.org/std/fs/struct.File.html#method.open
-    // add() uses text as std::path::Path
-    // apply() uses text as std::path::Path
-    let new = text.replace(old_value, new_value);
-    String::from(new)
+/// Paths are compared component by component (`/home/jo` is not a base of `/home/joe/.bashrc`)
+/// and only the leading `old_base` is replaced. Returns `None` if `path` is outside `old_base`
+fn swap_path_bases(path: &Path, old_base: &Path, new_base: &Path) -> Option<PathBuf> {
+    let rel_path = path.strip_prefix(old_base).ok()?;
+    if rel_path.as_os_str().is_empty() {
+        return Some(new_base.to_path_buf());
+    }
+    Some(new_base.join(rel_path))
 }
 
-/// Saves defaults (like `sync_dir`, etc.) in config `config` for further use
-pub fn init(sync_dir: &str, config: &Config) -> Result<(), String> {
-    let abs_sync_dir = if std::path::Path::new(&sync_dir).is_absolute() {
-        PathBuf::from(sync_dir)
-    } else {
-        let mut abs_dst = std::env::current_dir().map_err(|e| e.to_string())?;
-        abs_dst.push(sync_dir);
-        abs_dst
-    };
+/// Returns `path` joined to current dir unless it's absolute
+pub(crate) fn absolute(path: &Path) -> Result<PathBuf, Error> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    let current_dir =
+        std::env::current_dir().map_err(|e| Error::io("Can't get current dir", e))?;
+    Ok(current_dir.join(path))
+}
+
+/// Name of file marking sync-dir dir which is symlinked as a whole (instead of each file inside)
+pub const LINKED_DIR_MARKER: &str = ".ffdir";
 
-    // next two lines can't be merged because of borrow error which i can't resolvec yet
+/// Returns `true` if sync-dir `dir` is symlinked as a whole (see: `Options::link_dirs`)
+pub fn is_linked_dir(dir: &Path) -> bool {
+    dir.join(LINKED_DIR_MARKER).is_file()
+}
+
+/// Options of `add`, `remove`, `apply`, `pull` and `status`
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// only plan operations, change nothing
+    pub dry_run: bool,
+    /// skip failing files instead of reverting all changes
+    pub keep_going: bool,
+    /// what `apply` does with home-dir files which differ from sync-dir ones
+    pub on_conflict: ConflictPolicy,
+    /// paths (relative to sync-dir) skipped by `apply`
+    pub to_ignore: Vec<String>,
+    /// create symlinks relative to their location instead of absolute ones
+    pub relative_links: bool,
+    /// make `add` symlink dirs as a whole instead of each file they contain
+    pub link_dirs: bool,
+    /// make `remove` replace also symlinks pointing outside sync-dir
+    pub force: bool,
+    /// make `remove` replace symlinks with copies of their targets (sync-dir files are left)
+    pub copy_back: bool,
+    /// make `apply` move existing home-dir files over sync-dir ones before symlinking them
+    pub adopt: bool,
+    /// make `add` and `remove` commit their changes when sync-dir is in git repository
+    /// (see: `ff::git::commit_report`)
+    pub git_commit: bool,
+    /// variables used by `apply` to render templates (see: `ff::template`)
+    pub vars: Vars,
+    /// patterns (`.gitignore` syntax, relative to sync-dir) of files which `apply` copies
+    /// instead of symlinking them (see: `ff::core::pull`)
+    pub copy: Vec<String>,
+    /// make `add` store files encrypted (see: `ff::secret`)
+    pub encrypt: bool,
+    /// passphrase of secrets, asked for when it's needed and not given
+    pub passphrase: Option<Passphrase>,
+}
+
+impl Options {
+    /// Gives options with defaults saved in `config`
+    /// (`on-conflict`, `ignore-when-apply`, `relative-links`, `copy`, `git-commit`, `[vars]`)
+    /// and passphrase of secrets set in environment (see: `ff::secret::PASSPHRASE_VAR`)
+    pub fn from_config(config: &Config) -> Result<Options, Error> {
+        Ok(Options {
+            on_conflict: config.get_on_conflict()?,
+            to_ignore: config.get_ignored()?,
+            relative_links: config.get_bool("relative-links")?,
+            vars: Vars::from_config(config)?,
+            copy: config.get_copied()?,
+            git_commit: config.get_bool("git-commit")?,
+            passphrase: Passphrase::from_env(),
+            ..Options::default()
+        })
+    }
+}
+
+/// Returns target of symlink `link` pointing to `sync_file`
+///
+/// Target is relative to dir of `link` if `options.relative_links` is set
+fn link_target(sync_file: &Path, link: &Path, options: &Options) -> PathBuf {
+    match link.parent() {
+        Some(dir) if options.relative_links => relative_path(dir, sync_file),
+        _ => sync_file.to_path_buf(),
+    }
+}
+
+/// Saves defaults (like `sync_dir`, etc.) in config `config` for further use
+///
+/// `sync_dir` has to be inside `home`. When `dry_run` is set config is left untouched
+pub fn init(sync_dir: &Path, home: &Home, config: &Config, dry_run: bool) -> Result<Report, Error> {
+    let abs_sync_dir = absolute(sync_dir)?;
     let abs_sync_dir = fs::canonicalize(&abs_sync_dir)
-        .map_err(|e| format!("Can't canonicalize: {:?} ({})", &abs_sync_dir, e))?;
-    let abs_sync_dir = abs_sync_dir
-        .as_path()
-        .to_str()
-        .ok_or_else(|| format!("Can't convert to str: {:?}", &abs_sync_dir))?;
+        .map_err(|e| Error::io(format!("Can't canonicalize: {:?}", &abs_sync_dir), e))?;
+    if !home.contains(&abs_sync_dir) {
+        return Err(Error::invalid_path(
+            abs_sync_dir,
+            "Sync dir should be descendant of home dir",
+        ));
+    }
 
-    let old_path = config.get("sync-dir")?;
+    let mut report = Report::new("init");
+    if dry_run {
+        report.push(
+            &abs_sync_dir,
+            Outcome::Planned,
+            format!("set config: sync-dir={} (dir to store dot-files in)", abs_sync_dir.display()),
+            None,
+        );
+        return Ok(report);
+    }
+    let old_path = config.get_path_value("sync-dir")?;
     config.set("ignore-when-apply", ".git/,.hg/")?;
-    config.set("sync-dir", abs_sync_dir)?;
+    if config.get("on-conflict")?.is_none() {
+        config.set("on-conflict", &ConflictPolicy::default().to_string())?;
+    }
+    config.set_path_value("sync-dir", &abs_sync_dir)?;
+    let mut message = format!("Set sync-dir to: {:?}", abs_sync_dir);
     if let Some(p) = old_path {
         if p != abs_sync_dir {
-            println!("Sync-dir overwritten (old value was: {:?})", p);
+            message = format!("Sync-dir overwritten (old value was: {:?})\n{}", p, message);
         };
     };
-    println!("Set sync-dir to: {:?}", abs_sync_dir);
-    Ok(())
+    report.push(abs_sync_dir, Outcome::Done, message, None);
+    Ok(report)
 }
 
-/// Adds `file_path` to `sync-dir`
-///
-/// New path is calculated by replacing `home_dir` with `sync_dir` in `file_path`
-pub fn add(file_path: &str, home_dir: &str, sync_dir: &str) -> Result<(), String> {
-    let mut abs_dst = std::env::current_dir().map_err(|e| e.to_string())?;
-    abs_dst.push(file_path);
-    let abs_dst = match abs_dst.as_path().to_str() {
-        None => return Err(format!("Can't get absolute dir for: {:?}", abs_dst)),
-        Some(v) => swap_path_bases(v, home_dir, sync_dir),
-    };
+/// Plans adding `file` to `sync_dir` (see: `ff::core::add` for details)
+pub fn plan_add(
+    file: &Path,
+    home: &Home,
+    sync_dir: &SyncDir,
+    options: &Options,
+) -> Result<Plan, Error> {
+    let file = absolute(file)?;
+    let abs_dst = swap_path_bases(&file, home.path(), sync_dir.path())
+        .ok_or_else(|| Error::invalid_path(&file, "File should be inside home dir"))?;
+    if options.encrypt {
+        return plan_encrypt(&file, &encrypted_path(&abs_dst), options);
+    }
 
-    let abs_dst_parent = Path::new(&abs_dst)
+    let abs_dst_parent = abs_dst
         .parent()
-        .and_then(|p| p.to_str())
-        .ok_or_else(|| format!("Can't get parent of: {:?}", &abs_dst))?;
+        .ok_or_else(|| Error::invalid_path(&abs_dst, "Can't get parent"))?;
+
+    let mut plan = Plan::for_file(&file);
+    if !abs_dst_parent.exists() {
+        plan.push(
+            Operation::CreateDir(abs_dst_parent.to_path_buf()),
+            "sync-dir lacks parent dir of added file",
+        );
+    }
+    plan.push(
+        Operation::Move {
+            from: file.clone(),
+            to: abs_dst.clone(),
+        },
+        "file is stored in sync-dir from now on",
+    );
+    let is_dir = fs::symlink_metadata(&file)
+        .map(|m| m.is_dir())
+        .unwrap_or(false);
+    if is_dir && !file.join(LINKED_DIR_MARKER).exists() {
+        plan.push(
+            Operation::WriteFile {
+                path: abs_dst.join(LINKED_DIR_MARKER),
+                contents: vec![],
+                mode: None,
+            },
+            "dir is symlinked as a whole by apply",
+        );
+    }
+    plan.push(
+        Operation::Symlink {
+            target: link_target(&abs_dst, &file, options),
+            link: file.clone(),
+        },
+        "home-dir path points to file stored in sync-dir",
+    );
+    plan.on_done(format!("added: {} (to: {})", file.display(), abs_dst.display()));
+    Ok(plan)
+}
 
-    if let Err(e) = fs::create_dir_all(abs_dst_parent) {
-        return Err(format!("Can't create sync-dir: {}", e.to_string()));
+/// Plans storing `file` encrypted with `options.passphrase` as `sync_file` (see: `ff::secret`)
+///
+/// Home-dir file is left as it is, existing `sync_file` is replaced (secret is updated)
+fn plan_encrypt(file: &Path, sync_file: &Path, options: &Options) -> Result<Plan, Error> {
+    match fs::symlink_metadata(file) {
+        Ok(ref m) if m.is_file() => {}
+        _ => return Err(Error::invalid_path(file, "Only regular files can be encrypted")),
+    }
+    let contents = encrypt_file(file, options.passphrase.as_ref())?;
+    let mut plan = Plan::for_file(file);
+    match sync_file.parent() {
+        Some(dir) if !dir.exists() => plan.push(
+            Operation::CreateDir(dir.to_path_buf()),
+            "sync-dir lacks parent dir of added file",
+        ),
+        _ => {}
+    }
+    if fs::symlink_metadata(sync_file).is_ok() {
+        plan.push(
+            Operation::RemoveFile(sync_file.to_path_buf()),
+            "secret is replaced by newly encrypted file",
+        );
     }
+    plan.push(
+        Operation::WriteFile {
+            path: sync_file.to_path_buf(),
+            contents,
+            mode: None,
+        },
+        "file is stored encrypted in sync-dir (home-dir file is left)",
+    );
+    plan.on_done(format!("encrypted: {} (to: {})", file.display(), sync_file.display()));
+    Ok(plan)
+}
 
-    if let Err(e) = std::fs::rename(&file_path, &abs_dst) {
-        return Err(format!("Can't move file to sync-dir ({})", e));
+/// Returns `options` with passphrase asked for (see: `ff::secret::Passphrase::ask`)
+/// when it's `needed` and not given
+fn with_passphrase(options: &Options, needed: bool) -> Options {
+    let mut options = options.clone();
+    if needed && options.passphrase.is_none() {
+        options.passphrase = Passphrase::ask();
     }
+    options
+}
 
-    if let Err(e) = unix_fs::symlink(&abs_dst, &file_path) {
-        println!(
-            "Can't symlink moved file {} to {} ({})",
-            abs_dst, file_path, e
-        );
-        println!("Trying revert file move..");
-        if let Err(e) = std::fs::rename(&abs_dst, &file_path) {
-            return Err(format!(
-                "Can't revert file move ({}) - clean it MANUALLY ",
-                e
-            ));
-        } else {
-            return Err("File move reverted".to_owned());
+/// Returns files which are added when `file` is added to `sync_dir`
+///
+/// Dir gives files it contains (recursively, symlinks are left as they are),
+/// unless `options.link_dirs` is set
+fn files_to_add(file: &Path, sync_dir: &SyncDir, options: &Options) -> Result<Vec<PathBuf>, Error> {
+    let file = absolute(file)?;
+    match fs::symlink_metadata(&file) {
+        Ok(ref m) if m.is_dir() => {}
+        _ => return Ok(vec![file]),
+    }
+    if sync_dir.path().starts_with(&file) {
+        return Err(Error::invalid_path(file, "Can't add dir containing sync-dir"));
+    }
+    if options.link_dirs {
+        return Ok(vec![file]);
+    }
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&file) {
+        let entry = entry.map_err(|e| Error::invalid_path(&file, e.to_string()))?;
+        if entry.file_type().is_file() {
+            files.push(entry.path().to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Adds all `files` to `sync_dir`
+///
+/// New path is calculated by replacing `home` with `sync_dir` in file path,
+/// the file is moved there and symlinked back (see: `Options::relative_links`).
+/// Dirs are added file by file, or symlinked as a whole when `options.link_dirs` is set
+/// (see: `is_linked_dir`).
+/// When `options.dry_run` is set operations are only planned.
+/// Failure reverts all added files unless `options.keep_going` is set
+/// (see: `ff::journal::run_plans` for details and reported outcome).
+/// Permissions of added files and their parent dirs are recorded (see: `ff::modes`).
+/// With `options.encrypt` files are stored encrypted and left in home-dir (see: `ff::secret`).
+/// Changes are committed when `options.git_commit` is set.
+/// Files replaced by the changes are kept until `ff::report::Report::commit`
+/// (or `ff::history::History::record`)
+pub fn add<P: AsRef<Path>>(
+    files: &[P],
+    home: &Home,
+    sync_dir: &SyncDir,
+    options: &Options,
+) -> Result<Report, Error> {
+    let options = &with_passphrase(options, options.encrypt);
+    let mut report = Report::new("add");
+    let mut planned = Vec::new();
+    for file in files {
+        match files_to_add(file.as_ref(), sync_dir, options) {
+            Err(e) => planned.push((file.as_ref().to_path_buf(), Err(e))),
+            Ok(paths) => planned.extend(paths.into_iter().map(|path| {
+                let plan = plan_add(&path, home, sync_dir, options);
+                (path, plan)
+            })),
         }
     }
-    println!("added: {} (to: {})", file_path, abs_dst);
+    let plans = collect_plans(planned.into_iter(), options.keep_going, &mut report);
+    run_or_preview(&plans, options, &mut report);
+    record_modes(home, sync_dir, &report.done)?;
+    let mut secrets = Vec::new();
+    for done in &report.done {
+        if let Done::Written(ref sync_file) = *done {
+            if is_encrypted(sync_file) {
+                secrets.push((sync_file.clone(), home_path_for(sync_file, sync_dir, home)?));
+            }
+        }
+    }
+    let mut copies = Copies::load(home.copies_file())?;
+    if record_secrets(&mut copies, &secrets)? {
+        copies.save()?;
+    }
+    if options.git_commit {
+        commit_report(sync_dir.path(), &mut report);
+    }
+    Ok(report)
+}
+
+/// Records permissions of files moved to `sync_dir` by `done` operations and of their
+/// home-dir parent dirs in manifest of `sync_dir` (see: `ff::modes::Modes`)
+fn record_modes(home: &Home, sync_dir: &SyncDir, done: &[Done]) -> Result<(), Error> {
+    let mut modes = Modes::load(sync_dir)?;
+    let mut changed = false;
+    for item in done {
+        let rel_path = match *item {
+            Done::Moved { ref to, .. } => match to.strip_prefix(sync_dir.path()) {
+                Ok(v) => v,
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        if let Some(mode) = mode_of(&sync_dir.path().join(rel_path)) {
+            changed |= modes.set(rel_path, mode);
+        }
+        for dir in rel_path.ancestors().skip(1).take_while(|d| !d.as_os_str().is_empty()) {
+            if let Some(mode) = mode_of(&home.path().join(dir)) {
+                changed |= modes.set(dir, mode);
+            }
+        }
+    }
+    if changed {
+        modes.save()?;
+    }
     Ok(())
 }
 
-/// Adds all files contained in `file_paths` to `sync-dir`
-/// (see: `ff::core::add` for details)
-pub fn add_files(file_paths: &[&str], home_dir: &str, sync_dir: &str) {
-    for file_path in file_paths {
-        if let Err(e) = add(file_path, home_dir, sync_dir) {
-            println!("{}", e);
+/// Gathers successfully built plans, failed ones are reported into `report`
+///
+/// Failed ones are skipped if `keep_going` is set, otherwise nothing is returned
+fn collect_plans<I>(plans: I, keep_going: bool, report: &mut Report) -> Vec<Plan>
+where
+    I: Iterator<Item = (PathBuf, Result<Plan, Error>)>,
+{
+    let mut collected = Vec::new();
+    for (path, plan) in plans {
+        match plan {
+            Ok(v) => collected.push(v),
+            Err(e) => {
+                report.push(path, Outcome::Failed, "", Some(e));
+                if !keep_going {
+                    return vec![];
+                }
+            }
         }
     }
+    collected
 }
 
-/// Removes `symlink` and replace it with its target
-pub fn remove(symlinked: &str) -> Result<(), String> {
-    let regular_file =
-        fs::read_link(&symlinked).map_err(|e| format!("Can't read symlink: ({})", e))?;
-    if let Err(e) = fs::rename(&regular_file, &symlinked) {
-        return Err(format!(
-            "Can't move file {:?} to {:?} ({})",
-            &regular_file, &symlinked, e
-        ));
+/// Previews `plans` if `options.dry_run` is set, otherwise runs them
+/// (see: `ff::journal::run_plans` for details)
+fn run_or_preview(plans: &[Plan], options: &Options, report: &mut Report) {
+    if options.dry_run {
+        preview_plans(plans, report);
+    } else {
+        run_plans(plans, options.keep_going, report);
+    }
+}
+
+/// Returns `true` if symlink `link` points to `file` (relative targets are followed)
+fn links_to(link: &Path, file: &Path) -> bool {
+    match (resolve_link(link).and_then(fs::canonicalize), fs::canonicalize(file)) {
+        (Ok(target), Ok(file)) => target == file,
+        _ => false,
+    }
+}
+
+/// Returns `true` if symlink `link` points to `file` and its target is relative
+/// only when `options.relative_links` is set (so it needn't be created again)
+fn is_linked(link: &Path, file: &Path, options: &Options) -> bool {
+    links_to(link, file)
+        && fs::read_link(link)
+            .map(|target| target.is_relative() == options.relative_links)
+            .unwrap_or(false)
+}
+
+/// Returns `true` if symlink `link` points to file inside `sync_dir`
+///
+/// Relative targets (also with `..`) and symlinked dirs on the way are followed
+fn points_into(link: &Path, sync_dir: &SyncDir) -> bool {
+    let target = match resolve_link(link) {
+        Err(_) => return false,
+        Ok(v) => v,
+    };
+    let target = match (target.parent(), target.file_name()) {
+        (Some(dir), Some(name)) => match fs::canonicalize(dir) {
+            Err(_) => return false,
+            Ok(dir) => dir.join(name),
+        },
+        _ => return false,
+    };
+    let sync_dir =
+        fs::canonicalize(sync_dir.path()).unwrap_or_else(|_| sync_dir.path().to_path_buf());
+    target.starts_with(sync_dir)
+}
+
+/// Plans replacing copy of `regular_file` (file or dir) with `symlinked`
+/// (see: `Options::copy_back`)
+fn plan_copy_back(regular_file: &Path, symlinked: &Path, plan: &mut Plan) -> Result<(), Error> {
+    plan.push(
+        Operation::RemoveFile(symlinked.to_path_buf()),
+        "symlink is replaced with copy of file it points to",
+    );
+    if !regular_file.is_dir() {
+        plan.push(
+            Operation::CopyFile {
+                from: regular_file.to_path_buf(),
+                to: symlinked.to_path_buf(),
+            },
+            "sync-dir file is left in place",
+        );
+        return Ok(());
+    }
+    for entry in WalkDir::new(regular_file) {
+        let entry = entry.map_err(|e| Error::invalid_path(regular_file, e.to_string()))?;
+        if entry.file_name() == LINKED_DIR_MARKER {
+            continue;
+        }
+        let copy = swap_path_bases(entry.path(), regular_file, symlinked)
+            .ok_or_else(|| Error::invalid_path(entry.path(), "File should be inside dir"))?;
+        if entry.file_type().is_dir() {
+            plan.push(Operation::CreateDir(copy), "dir is copied back");
+        } else {
+            plan.push(
+                Operation::CopyFile {
+                    from: entry.path().to_path_buf(),
+                    to: copy,
+                },
+                "sync-dir file is left in place",
+            );
+        }
     }
-    println!("removed: {} (from: {:?})", symlinked, regular_file);
     Ok(())
 }
 
-/// Removes all files contained in `file_paths` from `sync-dir`
-/// (see: `ff::core::remove` for details)
-pub fn remove_files(file_paths: &[&str]) {
-    for file_path in file_paths {
-        if let Err(e) = remove(file_path) {
-            println!("{}", e);
+/// Plans removing `symlinked` (see: `ff::core::remove` for details)
+pub fn plan_remove(symlinked: &Path, sync_dir: &SyncDir, options: &Options) -> Result<Plan, Error> {
+    let symlinked = absolute(symlinked)?;
+    let regular_file = resolve_link(&symlinked)
+        .map_err(|e| Error::io(format!("Can't read symlink: {:?}", symlinked), e))?;
+    if !options.force && !points_into(&symlinked, sync_dir) {
+        return Err(Error::invalid_path(
+            &symlinked,
+            format!("Symlink doesn't point inside sync-dir {:?}", sync_dir.path()),
+        ));
+    }
+    let mut plan = Plan::for_file(&symlinked);
+    if options.copy_back {
+        plan_copy_back(&regular_file, &symlinked, &mut plan)?;
+        plan.on_done(format!(
+            "removed: {} (copied from: {:?})",
+            symlinked.display(),
+            regular_file
+        ));
+        return Ok(plan);
+    }
+    if regular_file.is_dir() {
+        // dir can't replace symlink, so it's removed first
+        plan.push(
+            Operation::RemoveFile(symlinked.clone()),
+            "symlink is replaced with dir it points to",
+        );
+    }
+    plan.push(
+        Operation::Move {
+            from: regular_file.clone(),
+            to: symlinked.clone(),
+        },
+        "symlink is replaced with file it points to",
+    );
+    if is_linked_dir(&regular_file) {
+        plan.push(
+            Operation::RemoveFile(symlinked.join(LINKED_DIR_MARKER)),
+            "dir isn't symlinked as a whole anymore",
+        );
+    }
+    plan.on_done(format!(
+        "removed: {} (from: {:?})",
+        symlinked.display(),
+        regular_file
+    ));
+    Ok(plan)
+}
+
+/// Returns `true` if `path` is a glob pattern (contains `*`, `?` or `[`)
+fn is_pattern(path: &Path) -> bool {
+    path.as_os_str()
+        .as_bytes()
+        .iter()
+        .any(|b| [b'*', b'?', b'['].contains(b))
+}
+
+/// Returns home-dir symlink pointing to `sync_file` (file or dir symlinked as a whole)
+///
+/// Sync-subdir of `sync_file` isn't known, so the symlink is looked for at `home` paths
+/// made of path of `sync_file` relative to `sync_dir` with leading dirs dropped one by one
+fn link_to(sync_file: &Path, sync_dir: &SyncDir, home: &Home) -> Option<PathBuf> {
+    let rel_path = sync_file.strip_prefix(sync_dir.path()).ok()?;
+    (0..rel_path.components().count())
+        .map(|skipped| home.path().join(rel_path.components().skip(skipped).collect::<PathBuf>()))
+        .find(|link| file_state(sync_file, link) == FileState::Linked)
+}
+
+/// Returns home-dir symlinks (paired with their targets) pointing to files
+/// (or dirs symlinked as a whole) stored in `sync_dir`
+pub fn managed_links(
+    home: &Home,
+    sync_dir: &SyncDir,
+    options: &Options,
+) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    Ok(walk_sync_dir(sync_dir, &options.to_ignore)?
+        .iter()
+        .filter(|e| !e.file_type().is_dir() || (e.depth() > 0 && is_linked_dir(e.path())))
+        .filter_map(|e| link_to(e.path(), sync_dir, home).map(|link| (link, e.path().to_path_buf())))
+        .collect())
+}
+
+/// Returns symlinks which are removed when `file` is removed from `sync_dir`
+///
+/// `file` is one of:
+///
+/// * home-dir symlink, dir (not symlinked as a whole) gives symlinks it contains (recursively),
+///   those pointing outside `sync_dir` are skipped unless `options.force` is set
+/// * sync-dir file or dir, gives symlinks pointing to it (or files inside it)
+/// * glob pattern (`.gitignore` syntax), gives symlinks whose path or target matches it
+///
+/// `managed` are symlinks pointing inside `sync_dir` (see: `managed_links`)
+fn links_to_remove(
+    file: &Path,
+    sync_dir: &SyncDir,
+    managed: &[(PathBuf, PathBuf)],
+    options: &Options,
+) -> Result<Vec<PathBuf>, Error> {
+    let file = absolute(file)?;
+    if is_pattern(&file) {
+        let pattern = file.to_string_lossy();
+        let mut builder = GitignoreBuilder::new("/");
+        builder
+            .add_line(None, &pattern)
+            .map_err(|e| Error::InvalidArgument(format!("Invalid pattern: {:?} ({})", pattern, e)))?;
+        let matcher = builder
+            .build()
+            .map_err(|e| Error::InvalidArgument(format!("Invalid pattern: {:?} ({})", pattern, e)))?;
+        let links = managed
+            .iter()
+            .filter(|(link, target)| {
+                [link, target].iter().any(|path| {
+                    matcher
+                        .matched_path_or_any_parents(path, path.is_dir())
+                        .is_ignore()
+                })
+            })
+            .map(|(link, _)| link.clone())
+            .collect::<Vec<_>>();
+        if links.is_empty() {
+            return Err(Error::NotFound(format!("No symlink matches: {:?}", pattern)));
+        }
+        return Ok(links);
+    }
+    if file.starts_with(sync_dir.path()) {
+        let links = managed
+            .iter()
+            .filter(|(_, target)| target.starts_with(&file))
+            .map(|(link, _)| link.clone())
+            .collect::<Vec<_>>();
+        if links.is_empty() && !file.is_dir() {
+            return Err(Error::NotFound(format!("No symlink points to: {:?}", file)));
         }
+        return Ok(links);
     }
+    match fs::symlink_metadata(&file) {
+        Ok(ref m) if m.is_dir() => {}
+        _ => return Ok(vec![file]),
+    }
+    let mut links = Vec::new();
+    for entry in WalkDir::new(&file) {
+        let entry = entry.map_err(|e| Error::invalid_path(&file, e.to_string()))?;
+        if entry.file_type().is_symlink() && (options.force || points_into(entry.path(), sync_dir)) {
+            links.push(entry.path().to_path_buf());
+        }
+    }
+    Ok(links)
 }
 
-/// Calls `symlink_file` on each files contained in `to_walk`
-pub fn apply(
-    to_walk: &str,
-    sync_dir: &str,
-    home_dir: &str,
-    to_ignore: &[&str],
-) -> Result<(), String> {
-    'dir_item: for item_result in WalkDir::new(Path::new(&to_walk)) {
+/// Removes all `files` from `sync_dir`, each symlink is replaced with its target
+///
+/// `files` are home-dir symlinks, sync-dir files or glob patterns (see: `links_to_remove`).
+/// Dir symlinked as a whole is moved back (see: `is_linked_dir`), other dirs have
+/// each symlink they contain replaced.
+/// Symlinks pointing outside `sync_dir` are refused unless `options.force` is set.
+/// With `options.copy_back` symlinks are replaced with copies, sync-dir files are left.
+/// When `options.dry_run` is set operations are only planned.
+/// Failure reverts all removed files unless `options.keep_going` is set
+/// (see: `ff::journal::run_plans` for details and reported outcome).
+/// Changes are committed when `options.git_commit` is set.
+/// Files replaced by the changes are kept until `ff::report::Report::commit`
+/// (or `ff::history::History::record`)
+pub fn remove<P: AsRef<Path>>(
+    files: &[P],
+    home: &Home,
+    sync_dir: &SyncDir,
+    options: &Options,
+) -> Result<Report, Error> {
+    let mut report = Report::new("remove");
+    let needs_managed = files.iter().any(|file| {
+        let file = file.as_ref();
+        is_pattern(file) || absolute(file).map(|f| f.starts_with(sync_dir.path())).unwrap_or(false)
+    });
+    let managed = if needs_managed {
+        managed_links(home, sync_dir, options)?
+    } else {
+        vec![]
+    };
+    let mut planned = Vec::new();
+    for file in files {
+        match links_to_remove(file.as_ref(), sync_dir, &managed, options) {
+            Err(e) => planned.push((file.as_ref().to_path_buf(), Err(e))),
+            Ok(links) => {
+                for link in links {
+                    if planned.iter().any(|(planned_link, _)| *planned_link == link) {
+                        continue;
+                    }
+                    let plan = plan_remove(&link, sync_dir, options);
+                    planned.push((link, plan));
+                }
+            }
+        }
+    }
+    let plans = collect_plans(planned.into_iter(), options.keep_going, &mut report);
+    run_or_preview(&plans, options, &mut report);
+    if options.git_commit {
+        commit_report(sync_dir.path(), &mut report);
+    }
+    Ok(report)
+}
+
+/// Returns entries (files and dirs) contained in `sync_dir` except ignored ones
+///
+/// Ignored are paths listed in `.ffignore` files (see: `ff::ffignore::Ignored`)
+/// and those starting with one of `to_ignore` items (paths relative to `sync_dir`).
+/// Contents of linked dirs are skipped (see: `is_linked_dir`). Fails if any entry can't be read
+pub fn walk_sync_dir(sync_dir: &SyncDir, to_ignore: &[String]) -> Result<Vec<DirEntry>, Error> {
+    let mut ignored = Ignored::new(sync_dir.path(), to_ignore);
+    let mut entries = Vec::new();
+    let mut iter = WalkDir::new(sync_dir.path()).into_iter();
+    while let Some(item_result) = iter.next() {
         let sync_file = match item_result {
             Err(e) => {
-                println!("SKIPPING ({})", e);
-                continue;
+                let path = e.path().unwrap_or_else(|| sync_dir.path()).to_path_buf();
+                return Err(Error::io(format!("Can't read {:?}", path), e.into()));
             }
             Ok(v) => v,
         };
+        let is_dir = sync_file.file_type().is_dir();
+        if ignored.is_ignored(sync_file.path(), is_dir) {
+            if is_dir {
+                iter.skip_current_dir();
+            }
+            continue;
+        }
+        if is_dir && sync_file.depth() > 0 && is_linked_dir(sync_file.path()) {
+            iter.skip_current_dir();
+        } else if is_dir {
+            ignored.load_dir(sync_file.path())?;
+        }
+        entries.push(sync_file);
+    }
+    Ok(entries)
+}
 
-        let rel_sync_file = sync_file
-            .path()
-            .to_str()
-            .ok_or_else(|| format!("Can't get str for: {:?}", &sync_file))?
-            .replace(sync_dir, "");
-        let rel_sync_file = rel_sync_file.trim_matches('/');
-        for ignore in to_ignore {
-            if rel_sync_file.starts_with(ignore) {
-                continue 'dir_item;
+/// Returns home-dir counterpart of `sync_file`
+///
+/// Path is `sync_file` moved from `sync_dir` to `home` (see: `swap_path_bases`),
+/// templates and secrets lose their suffix (see: `ff::template::rendered_path`,
+/// `ff::secret::decrypted_path`)
+pub fn home_path_for(sync_file: &Path, sync_dir: &SyncDir, home: &Home) -> Result<PathBuf, Error> {
+    let user_file = swap_path_bases(sync_file, sync_dir.path(), home.path())
+        .ok_or_else(|| Error::invalid_path(sync_file, "File should be inside sync-dir"))?;
+    Ok(decrypted_path(&rendered_path(&user_file)))
+}
+
+/// Returns files and linked dirs (see: `is_linked_dir`) contained in `layers`,
+/// each with the layer it comes from
+///
+/// Later layers override earlier ones file by file, so a file is taken from the last layer
+/// which contains a file with the same path (relative to the layer)
+pub fn merge_layers<'a>(
+    layers: &'a [SyncDir],
+    to_ignore: &[String],
+) -> Result<Vec<(PathBuf, &'a SyncDir)>, Error> {
+    let mut merged: Vec<(PathBuf, &SyncDir)> = Vec::new();
+    let mut positions = collections::HashMap::new();
+    for layer in layers {
+        if !layer.path().is_dir() {
+            return Err(Error::NotFound(format!(
+                "Can't find sync-subdir: {:?}",
+                layer.path()
+            )));
+        }
+        for entry in walk_sync_dir(layer, to_ignore)? {
+            if entry.file_type().is_dir() && !(entry.depth() > 0 && is_linked_dir(entry.path())) {
+                continue;
             }
+            let rel_path = entry
+                .path()
+                .strip_prefix(layer.path())
+                .map_err(|_| Error::invalid_path(entry.path(), "Can't get relative path"))?
+                .to_path_buf();
+            let sync_file = (entry.path().to_path_buf(), layer);
+            match positions.get(&rel_path) {
+                Some(&i) => merged[i] = sync_file,
+                None => {
+                    positions.insert(rel_path, merged.len());
+                    merged.push(sync_file);
+                }
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Plans `symlink_file` for each file contained in `layers` (see: `merge_layers`)
+///
+/// Returns one `Plan` per file. Files which can't be planned are reported into `report`
+/// and skipped if `options.keep_going` is set, otherwise nothing is returned
+pub fn plan_apply(
+    home: &Home,
+    layers: &[SyncDir],
+    options: &Options,
+    backup: &Backup,
+    report: &mut Report,
+) -> Result<Vec<Plan>, Error> {
+    let sync_files = merge_layers(layers, &options.to_ignore)?;
+    let mut modes = Vec::new();
+    for layer in layers {
+        modes.push(Modes::load(layer)?);
+    }
+    let mut planned_dirs = collections::HashSet::new();
+    Ok(collect_plans(
+        sync_files.iter().map(|&(ref sync_file, layer)| {
+            let layer_modes = &modes[layers.iter().position(|l| l.path() == layer.path()).unwrap_or(0)];
+            let plan = plan_symlink_file(sync_file, layer, home, options, backup).and_then(|mut plan| {
+                plan_modes(sync_file, layer, home, options, layer_modes, &mut planned_dirs, &mut plan)?;
+                Ok(plan)
+            });
+            (sync_file.clone(), plan)
+        }),
+        options.keep_going,
+        report,
+    ))
+}
+
+/// Calls `symlink_file` on each file contained in `sync_dir`
+/// (see: `apply_layers` for details)
+///
+/// Files replaced by the changes are kept until `ff::report::Report::commit`
+/// (or `ff::history::History::record`)
+pub fn apply(home: &Home, sync_dir: &SyncDir, options: &Options) -> Result<Report, Error> {
+    apply_layers(home, slice::from_ref(sync_dir), options)
+}
+
+/// Calls `symlink_file` on each file contained in `layers` merged into one
+/// (see: `merge_layers`), e.g. sync-subdirs of a profile (see: `ff::paths::SyncDir::layers`)
+///
+/// Replaced home-dir files are backed up in `home` backups dir (see: `ff::backup::Backup`).
+/// Permissions recorded in sync-dir manifests are restored (see: `plan_modes`).
+/// When `options.dry_run` is set operations are only planned.
+/// Failure reverts all symlinked files unless `options.keep_going` is set
+/// (see: `ff::journal::run_plans` for details and reported outcome).
+/// Files replaced by the changes are kept until `ff::report::Report::commit`
+/// (or `ff::history::History::record`)
+pub fn apply_layers(home: &Home, layers: &[SyncDir], options: &Options) -> Result<Report, Error> {
+    let has_secrets = merge_layers(layers, &options.to_ignore)?
+        .iter()
+        .any(|(sync_file, _)| is_encrypted(sync_file));
+    let options = &with_passphrase(options, has_secrets);
+    let mut report = Report::new("apply");
+    let backup = Backup::new(home.backups_dir(), home.path());
+    let plans = plan_apply(home, layers, options, &backup, &mut report)?;
+    run_or_preview(&plans, options, &mut report);
+    if !options.dry_run {
+        record_copies(home, layers, options, &report.done)?;
+    }
+    if backup.get_dir().exists() {
+        report.notes.push(format!(
+            "Replaced files backed up to: {:?} (see: 'ff restore-backup')",
+            backup.get_dir()
+        ));
+    }
+    Ok(report)
+}
+
+/// Adds creating parent dir of `user_file` to `plan` if it's missing in home-dir
+fn plan_parent_dir(user_file: &Path, plan: &mut Plan) -> Result<(), Error> {
+    let user_file_dir = user_file
+        .parent()
+        .ok_or_else(|| Error::invalid_path(user_file, "Can't get parent dir for file"))?;
+    if !user_file_dir.exists() {
+        plan.push(
+            Operation::CreateDir(user_file_dir.to_path_buf()),
+            "home-dir lacks parent dir of synced file",
+        );
+    }
+    Ok(())
+}
+
+/// Adds removing existing `user_file` to `plan`, so it can be replaced by `replacement`
+///
+/// `user_file` which differs from `sync_file` (`differs` is set) is handled according to
+/// `options.on_conflict` (`interactive` one doesn't ask with `options.dry_run`).
+/// Returns `false` when `user_file` has to be left (plan is skipped)
+fn plan_replace(
+    user_file: &Path,
+    sync_file: &Path,
+    differs: bool,
+    replacement: &str,
+    options: &Options,
+    backup: &Backup,
+    plan: &mut Plan,
+) -> Result<bool, Error> {
+    let policy = if !differs {
+        ConflictPolicy::Overwrite
+    } else if options.on_conflict == ConflictPolicy::Interactive && options.dry_run {
+        // answer would be thrown away, so preview only says it will be asked for
+        plan.push(
+            Operation::RemoveFile(user_file.to_path_buf()),
+            "conflict: would ask",
+        );
+        return Ok(true);
+    } else if options.on_conflict == ConflictPolicy::Interactive {
+        ask(user_file, sync_file)
+    } else {
+        options.on_conflict
+    };
+    match policy {
+        ConflictPolicy::Skip => {
+            plan.skip(format!(
+                "{:?} differs from {:?} (conflict)",
+                user_file, sync_file
+            ));
+            return Ok(false);
+        }
+        ConflictPolicy::Backup => backup.plan_backup(user_file, plan)?,
+        _ => plan.push(
+            Operation::RemoveFile(user_file.to_path_buf()),
+            format!("home-dir file is replaced by {}", replacement),
+        ),
+    }
+    Ok(true)
+}
+
+/// Plans `symlink_file` (see: `ff::core::symlink_file` for details)
+///
+/// Plan is empty when `sync_file` is not a file or it's symlinked already.
+/// Templates are rendered, secrets decrypted and files matching `options.copy`
+/// are copied instead of being symlinked
+/// (see: `plan_render_file`, `plan_decrypt_file`, `plan_copy_file`).
+/// With `options.adopt` existing home-dir file is moved over `sync_file` first (like `add` does)
+pub fn plan_symlink_file(
+    sync_file: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    backup: &Backup,
+) -> Result<Plan, Error> {
+    let content_item_data = std::fs::metadata(sync_file)
+        .map_err(|e| Error::io(format!("Can't get file data {:?}", sync_file), e))?;
+    if content_item_data.is_dir() && is_linked_dir(sync_file) {
+        return plan_link_dir(sync_file, sync_dir, home, options, backup);
+    }
+    if !content_item_data.is_file() {
+        return Ok(Plan::new());
+    }
+    if is_template(sync_file) {
+        return plan_render_file(sync_file, sync_dir, home, options, backup);
+    }
+    if is_encrypted(sync_file) {
+        return plan_decrypt_file(sync_file, sync_dir, home, options, backup);
+    }
+    if is_copied(sync_file, sync_dir, &options.copy)? {
+        return plan_copy_file(sync_file, sync_dir, home, options, backup);
+    }
+    let user_file = home_path_for(sync_file, sync_dir, home)?;
+    let mut plan = Plan::for_file(&user_file);
+    plan_parent_dir(&user_file, &mut plan)?;
+    if let Ok(metadata) = fs::symlink_metadata(&user_file) {
+        // reached through symlinked parent dir, so it's the sync-dir file itself
+        if !metadata.file_type().is_symlink()
+            && fs::canonicalize(&user_file).ok() == fs::canonicalize(sync_file).ok()
+        {
+            return Ok(plan);
+        }
+        if metadata.file_type().is_symlink() && is_linked(&user_file, sync_file, options) {
+            return Ok(plan);
+        }
+        if options.adopt && metadata.is_file() {
+            plan.push(
+                Operation::Move {
+                    from: user_file.clone(),
+                    to: sync_file.to_path_buf(),
+                },
+                "home-dir file replaces sync-dir one (adopted)",
+            );
+            plan.push(
+                Operation::Symlink {
+                    target: link_target(sync_file, &user_file, options),
+                    link: user_file.clone(),
+                },
+                "home-dir path points to file stored in sync-dir",
+            );
+            plan.on_done(format!("adopted: {:?} (to: {:?})", user_file, sync_file));
+            return Ok(plan);
         }
+        let links_to_sync_file =
+            metadata.file_type().is_symlink() && links_to(&user_file, sync_file);
+        let differs = !links_to_sync_file && !same_contents(&user_file, sync_file);
+        if !plan_replace(&user_file, sync_file, differs, "symlink", options, backup, &mut plan)? {
+            return Ok(plan);
+        }
+    }
+    plan.push(
+        Operation::Symlink {
+            target: link_target(sync_file, &user_file, options),
+            link: user_file.clone(),
+        },
+        "home-dir path points to file stored in sync-dir",
+    );
+    plan.on_done(format!("symlinked: {:?} -> {:?}", user_file, sync_file));
+    Ok(plan)
+}
 
-        if let Err(e) = symlink_file(&sync_file, sync_dir, home_dir) {
-            println!("SKIPPING: {}", e);
+/// Returns files (and dirs) which should have permissions recorded in `modes` for `sync_file`,
+/// each with its mode
+///
+/// It's the file home-dir path resolves to (`sync_file` itself, unless it's rendered or copied)
+/// and its home-dir parent dirs. Secrets are always private (see: `ff::secret::SECRET_MODE`)
+pub fn recorded_modes(
+    sync_file: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    modes: &Modes,
+) -> Result<Vec<(PathBuf, u32)>, Error> {
+    let rel_path = sync_file
+        .strip_prefix(sync_dir.path())
+        .map_err(|_| Error::invalid_path(sync_file, "File should be inside sync-dir"))?;
+    let mut recorded = Vec::new();
+    for dir in rel_path.ancestors().skip(1) {
+        if dir.as_os_str().is_empty() {
+            break;
         }
+        if let Some(mode) = modes.get(dir) {
+            recorded.push((home.path().join(dir), mode));
+        }
+    }
+    recorded.reverse();
+    if is_encrypted(sync_file) {
+        return Ok(recorded);
+    }
+    if let Some(mode) = modes.get(rel_path) {
+        let written = sync_file.is_file()
+            && (is_template(sync_file) || is_copied(sync_file, sync_dir, &options.copy)?);
+        let path = if written {
+            home_path_for(sync_file, sync_dir, home)?
+        } else {
+            sync_file.to_path_buf()
+        };
+        recorded.push((path, mode));
+    }
+    Ok(recorded)
+}
+
+/// Adds restoring permissions recorded for `sync_file` (see: `recorded_modes`) to `plan`
+/// when they differ (or the file is created by the plan)
+///
+/// Parent dirs already in `planned_dirs` are left (several files share them).
+/// Skipped plan is left as it is
+pub fn plan_modes(
+    sync_file: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    modes: &Modes,
+    planned_dirs: &mut collections::HashSet<PathBuf>,
+    plan: &mut Plan,
+) -> Result<(), Error> {
+    if plan.skipped().is_some() {
+        return Ok(());
+    }
+    let was_empty = plan.is_empty();
+    let user_file = home_path_for(sync_file, sync_dir, home)?;
+    for (path, mode) in recorded_modes(sync_file, sync_dir, home, options, modes)? {
+        let is_parent_dir = path != user_file && user_file.starts_with(&path);
+        if is_parent_dir && !planned_dirs.insert(path.clone()) {
+            continue;
+        }
+        let created = plan.steps().iter().any(|step| match step.operation {
+            Operation::CreateDir(ref dir) => dir.starts_with(&path),
+            Operation::WriteFile { path: ref p, .. } => *p == path,
+            _ => false,
+        });
+        if created || mode_of(&path) != Some(mode) {
+            plan.push(
+                Operation::SetMode { path, mode },
+                "permissions are restored from sync-dir manifest",
+            );
+        }
+    }
+    if was_empty && !plan.is_empty() {
+        plan.on_done(format!("permissions restored: {:?}", plan.path()));
     }
     Ok(())
 }
 
+/// Plans symlinking `linked_dir` (see: `is_linked_dir`) to its home-dir counterpart
+///
+/// Plan is empty when it's symlinked already. Existing home-dir dir or file is backed up
+/// (dirs are never removed) unless `options.on_conflict` is `skip`, or user skips it
+/// when asked (`interactive`, see: `plan_replace`)
+pub fn plan_link_dir(
+    linked_dir: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    backup: &Backup,
+) -> Result<Plan, Error> {
+    let user_dir = home_path_for(linked_dir, sync_dir, home)?;
+    let mut plan = Plan::for_file(&user_dir);
+    plan_parent_dir(&user_dir, &mut plan)?;
+    if let Ok(metadata) = fs::symlink_metadata(&user_dir) {
+        if metadata.file_type().is_symlink() {
+            if is_linked(&user_dir, linked_dir, options) {
+                return Ok(plan);
+            }
+            plan.push(
+                Operation::RemoveFile(user_dir.clone()),
+                "home-dir symlink is replaced by dir symlink",
+            );
+        } else {
+            let policy = match options.on_conflict {
+                ConflictPolicy::Interactive if options.dry_run => {
+                    plan.skip(format!(
+                        "{:?} exists, but {:?} is symlinked as a whole (conflict: would ask)",
+                        user_dir, linked_dir
+                    ));
+                    return Ok(plan);
+                }
+                ConflictPolicy::Interactive => ask(&user_dir, linked_dir),
+                policy => policy,
+            };
+            if policy == ConflictPolicy::Skip {
+                plan.skip(format!(
+                    "{:?} exists, but {:?} is symlinked as a whole (conflict)",
+                    user_dir, linked_dir
+                ));
+                return Ok(plan);
+            }
+            backup.plan_backup(&user_dir, &mut plan)?;
+        }
+    }
+    plan.push(
+        Operation::Symlink {
+            target: link_target(linked_dir, &user_dir, options),
+            link: user_dir.clone(),
+        },
+        "home-dir path points to dir stored in sync-dir",
+    );
+    plan.on_done(format!("symlinked: {:?} -> {:?}", user_dir, linked_dir));
+    Ok(plan)
+}
+
+/// Plans writing template `sync_file` rendered with `options.vars` to its home-dir counterpart
+/// (e.g. `<sync-dir>/.gitconfig.tmpl` is rendered to `<home>/.gitconfig`)
+///
+/// Plan is empty when home-dir file is up to date. Home-dir file which differs from
+/// rendered template is handled according to `options.on_conflict`
+pub fn plan_render_file(
+    sync_file: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    backup: &Backup,
+) -> Result<Plan, Error> {
+    let rendered = render_file(sync_file, &options.vars)?;
+    let user_file = home_path_for(sync_file, sync_dir, home)?;
+    let mut plan = Plan::for_file(&user_file);
+    plan_parent_dir(&user_file, &mut plan)?;
+    if let Ok(metadata) = fs::symlink_metadata(&user_file) {
+        let is_symlink = metadata.file_type().is_symlink();
+        let up_to_date = fs::read(&user_file)
+            .map(|contents| contents == rendered.as_bytes())
+            .unwrap_or(false);
+        if up_to_date && !is_symlink {
+            return Ok(plan);
+        }
+        let differs = !up_to_date && !is_symlink;
+        if !plan_replace(&user_file, sync_file, differs, "rendered template", options, backup, &mut plan)? {
+            return Ok(plan);
+        }
+    }
+    plan.push(
+        Operation::WriteFile {
+            path: user_file.clone(),
+            contents: rendered.into_bytes(),
+            mode: None,
+        },
+        "home-dir file is rendered from template stored in sync-dir",
+    );
+    plan.on_done(format!("rendered: {:?} (from: {:?})", user_file, sync_file));
+    Ok(plan)
+}
+
+/// Plans writing secret `sync_file` decrypted with `options.passphrase` to its home-dir
+/// counterpart readable by the owner only (e.g. `<sync-dir>/.netrc.age` to `<home>/.netrc`)
+///
+/// Plan is empty when home-dir file is up to date, only its permissions are fixed if needed.
+/// Home-dir file which differs from decrypted secret is handled according to
+/// `options.on_conflict`, unless it's unchanged since it was decrypted
+/// (see: `ff::secret::secret_hash`)
+pub fn plan_decrypt_file(
+    sync_file: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    backup: &Backup,
+) -> Result<Plan, Error> {
+    let contents = decrypt_file(sync_file, options.passphrase.as_ref())?;
+    let user_file = home_path_for(sync_file, sync_dir, home)?;
+    let mut plan = Plan::for_file(&user_file);
+    plan_parent_dir(&user_file, &mut plan)?;
+    if let Ok(metadata) = fs::symlink_metadata(&user_file) {
+        let is_symlink = metadata.file_type().is_symlink();
+        let user_contents = fs::read(&user_file).ok();
+        let same = user_contents.as_ref() == Some(&contents);
+        if same && !is_symlink {
+            // e.g. left as it was by `add --encrypt`
+            if mode_of(&user_file) != Some(SECRET_MODE) {
+                plan.push(
+                    Operation::SetMode {
+                        path: user_file.clone(),
+                        mode: SECRET_MODE,
+                    },
+                    "decrypted secret is readable by the owner only",
+                );
+                plan.on_done(format!("made private: {:?}", user_file));
+            }
+            return Ok(plan);
+        }
+        let copies = Copies::load(home.copies_file())?;
+        let recorded = copies.get(&user_file).unwrap_or_default();
+        let unchanged = user_contents.is_some_and(|c| is_recorded_plaintext(recorded, &c));
+        let differs = !(same || unchanged || is_symlink);
+        if !plan_replace(&user_file, sync_file, differs, "decrypted secret", options, backup, &mut plan)? {
+            return Ok(plan);
+        }
+    }
+    plan.push(
+        Operation::WriteFile {
+            path: user_file.clone(),
+            contents,
+            mode: Some(SECRET_MODE),
+        },
+        "home-dir file is decrypted from secret stored in sync-dir",
+    );
+    plan.on_done(format!("decrypted: {:?} (from: {:?})", user_file, sync_file));
+    Ok(plan)
+}
+
+/// Plans writing copy of `sync_file` to its home-dir counterpart
+///
+/// Plan is empty when home-dir file is up to date. Home-dir file which differs from `sync_file`
+/// is handled according to `options.on_conflict`, unless it's unchanged since it was copied
+/// (see: `ff::copies::Copies`)
+pub fn plan_copy_file(
+    sync_file: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    backup: &Backup,
+) -> Result<Plan, Error> {
+    let contents =
+        fs::read(sync_file).map_err(|e| Error::io(format!("Can't read {:?}", sync_file), e))?;
+    let user_file = home_path_for(sync_file, sync_dir, home)?;
+    let mut plan = Plan::for_file(&user_file);
+    plan_parent_dir(&user_file, &mut plan)?;
+    if let Ok(metadata) = fs::symlink_metadata(&user_file) {
+        let is_symlink = metadata.file_type().is_symlink();
+        let user_contents = fs::read(&user_file).ok();
+        let same = user_contents.as_ref() == Some(&contents);
+        if same && !is_symlink {
+            return Ok(plan);
+        }
+        let links_to_sync_file = is_symlink && links_to(&user_file, sync_file);
+        let copies = Copies::load(home.copies_file())?;
+        let unchanged = !is_symlink
+            && user_contents.map(|c| hash(&c)).as_deref() == copies.get(&user_file);
+        let differs = !(same || links_to_sync_file || unchanged);
+        if !plan_replace(&user_file, sync_file, differs, "copy", options, backup, &mut plan)? {
+            return Ok(plan);
+        }
+    }
+    plan.push(
+        Operation::WriteFile {
+            path: user_file.clone(),
+            contents,
+            mode: None,
+        },
+        "home-dir file is a copy of file stored in sync-dir",
+    );
+    plan.on_done(format!("copied: {:?} (from: {:?})", user_file, sync_file));
+    Ok(plan)
+}
+
+/// Returns files contained in `layers` which are copied to home-dir (see: `Options::copy`),
+/// each with its home-dir counterpart
+fn copied_files(
+    home: &Home,
+    layers: &[SyncDir],
+    options: &Options,
+) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let mut copied = Vec::new();
+    if options.copy.is_empty() {
+        return Ok(copied);
+    }
+    for (sync_file, layer) in merge_layers(layers, &options.to_ignore)? {
+        if sync_file.is_dir() || is_template(&sync_file) || is_encrypted(&sync_file)
+            || !is_copied(&sync_file, layer, &options.copy)?
+        {
+            continue;
+        }
+        let user_file = home_path_for(&sync_file, layer, home)?;
+        copied.push((sync_file, user_file));
+    }
+    Ok(copied)
+}
+
+/// Records hashes of home-dir copies which are the same as their sync-dir files
+/// and of secrets decrypted by `done` operations (see: `ff::secret::record_secrets`)
+fn record_copies(home: &Home, layers: &[SyncDir], options: &Options, done: &[Done]) -> Result<(), Error> {
+    let mut copies = Copies::load(home.copies_file())?;
+    let mut secrets = Vec::new();
+    for (sync_file, layer) in merge_layers(layers, &options.to_ignore)? {
+        if !is_encrypted(&sync_file) {
+            continue;
+        }
+        let user_file = home_path_for(&sync_file, layer, home)?;
+        if done.contains(&Done::Written(user_file.clone())) {
+            secrets.push((sync_file, user_file));
+        }
+    }
+    let mut changed = record_secrets(&mut copies, &secrets)?;
+    for (sync_file, user_file) in copied_files(home, layers, options)? {
+        match fs::symlink_metadata(&user_file) {
+            Ok(ref m) if m.is_file() => {}
+            _ => continue,
+        }
+        let sync_hash = file_hash(&sync_file)?;
+        if file_hash(&user_file)? == sync_hash {
+            changed |= copies.set(&user_file, sync_hash);
+        }
+    }
+    if changed {
+        copies.save()?;
+    }
+    Ok(())
+}
+
+/// Plans copying `user_file` back to `sync_file` (see: `ff::core::pull` for details)
+pub fn plan_pull(sync_file: &Path, user_file: &Path, copies: &Copies) -> Result<Plan, Error> {
+    let mut plan = Plan::for_file(user_file);
+    match fs::symlink_metadata(user_file) {
+        Ok(ref m) if m.is_file() => {}
+        _ => return Ok(plan),
+    }
+    let contents =
+        fs::read(user_file).map_err(|e| Error::io(format!("Can't read {:?}", user_file), e))?;
+    let sync_hash = file_hash(sync_file)?;
+    if hash(&contents) == sync_hash {
+        return Ok(plan);
+    }
+    match copies.get(user_file) {
+        Some(recorded) if recorded == sync_hash => {}
+        Some(_) => {
+            plan.skip(format!(
+                "{:?} and {:?} were both modified since copied (conflict)",
+                user_file, sync_file
+            ));
+            return Ok(plan);
+        }
+        None => {
+            plan.skip(format!(
+                "{:?} differs from {:?}, but it wasn't copied by 'ff apply'",
+                user_file, sync_file
+            ));
+            return Ok(plan);
+        }
+    }
+    plan.push(
+        Operation::RemoveFile(sync_file.to_path_buf()),
+        "sync-dir file is replaced by home-dir copy",
+    );
+    plan.push(
+        Operation::WriteFile {
+            path: sync_file.to_path_buf(),
+            contents,
+            // e.g. executable bit of a script
+            mode: mode_of(user_file),
+        },
+        "home-dir copy was modified since copied",
+    );
+    plan.on_done(format!("pulled: {:?} (to: {:?})", user_file, sync_file));
+    Ok(plan)
+}
+
+/// Copies home-dir copies (see: `Options::copy`) modified since `apply` back to `layers`
+/// (the layer the file comes from, see: `merge_layers`)
+///
+/// Files modified in both home-dir and sync-dir are skipped (conflict).
+/// When `options.dry_run` is set operations are only planned.
+/// Failure reverts all pulled files unless `options.keep_going` is set
+/// (see: `ff::journal::run_plans` for details and reported outcome).
+/// Files replaced by the changes are kept until `ff::report::Report::commit`
+/// (or `ff::history::History::record`)
+pub fn pull(home: &Home, layers: &[SyncDir], options: &Options) -> Result<Report, Error> {
+    let mut report = Report::new("pull");
+    let copies = Copies::load(home.copies_file())?;
+    let copied = copied_files(home, layers, options)?;
+    let plans = collect_plans(
+        copied.iter().map(|(sync_file, user_file)| {
+            (user_file.clone(), plan_pull(sync_file, user_file, &copies))
+        }),
+        options.keep_going,
+        &mut report,
+    );
+    run_or_preview(&plans, options, &mut report);
+    if !options.dry_run {
+        record_copies(home, layers, options, &[])?;
+    }
+    Ok(report)
+}
+
 /// Symlinks `sync_file` to its counterpart in homedir
 /// Homedir path is calculated by replacing `sync_dir` in `home_dir`
 ///
@@ -179,7 +1397,7 @@ pub fn apply(
 /// Assuming that there is a `.bashrc` file in `/home/joe/sync-dir` dir
 ///
 /// `symlink_file(
-///    &WalkDir::DirEntry("/home/joe/sync-dir/.bashrc"), "/home/joe/sync-dir", "/home/joe"
+///    Path::new("/home/joe/sync-dir/.bashrc"), &SyncDir::new("/home/joe/sync-dir"), ..
 /// )`
 ///
 /// will result as replacing:
@@ -190,38 +1408,16 @@ pub fn apply(
 ///
 /// `/home/joe/sync-dir/.bashrc`
 ///
-pub fn symlink_file(sync_file: &DirEntry, sync_dir: &str, home_dir: &str) -> Result<(), String> {
-    let content_item_data = std::fs::metadata(sync_file.path())
-        .map_err(|e| format!("Can't get file data {:?} ({})", &sync_file, e))?;
-    if !content_item_data.is_file() {
-        return Ok(());
-    }
-    let src_path = sync_file
-        .path()
-        .to_str()
-        .ok_or_else(|| format!("Can't convert src file: {:?}", &sync_file))?;
-    let user_file = swap_path_bases(src_path, sync_dir, home_dir);
-    let user_file = Path::new(&user_file);
-    let user_file_dir = user_file
-        .parent()
-        .and_then(|p| p.to_str())
-        .ok_or_else(|| format!("Can't get parent dir for file: {:?}", &user_file))?;
-    if let Err(e) = fs::create_dir_all(user_file_dir) {
-        return Err(format!("Can't create dir: {} ({})", user_file_dir, e));
-    }
-    if user_file.exists() {
-        if let Err(e) = std::fs::remove_file(&user_file) {
-            return Err(format!("Can't remove {:?} ({})", &user_file, e));
-        }
-    }
-    if let Err(e) = unix_fs::symlink(&sync_file.path(), &user_file) {
-        return Err(format!(
-            "Can't symlink {:?} to {:?} ({})",
-            sync_file, user_file, e
-        ));
-    }
-    println!("symlinked: {:?} -> {:?}", user_file, sync_file.path());
-    Ok(())
+/// Home-dir file which differs from `sync_file` is handled according to `options.on_conflict`
+/// (`backup` is used when file is backed up)
+pub fn symlink_file(
+    sync_file: &Path,
+    sync_dir: &SyncDir,
+    home: &Home,
+    options: &Options,
+    backup: &Backup,
+) -> Result<(), Error> {
+    plan_symlink_file(sync_file, sync_dir, home, options, backup)?.run()
 }
 
 #[cfg(test)]
@@ -230,6 +1426,7 @@ mod tests {
     use std::fs::File;
     use std::fs;
     use std::io::prelude::*;
+    use std::ffi::OsString;
     use std::path::Path;
     use std::os::unix::fs as unix_fs;
     use tempdir::TempDir;
@@ -240,11 +1437,11 @@ mod tests {
         let config_dir = TempDir::new_in(homedir.path(), ".ff").unwrap();
         let config_file = config_dir.path().join("config.ini");
         let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
-        let config = Config::new(config_file.as_path().to_str().unwrap()).unwrap();
+        let config = Config::new(&config_file).unwrap();
 
-        let result = init(sync_dir.path().to_str().unwrap(), &config).unwrap();
+        let result = init(sync_dir.path(), &Home::new(homedir.path()), &config, false).unwrap();
 
-        assert_eq!(result, ());
+        assert_eq!(result.records.len(), 1);
         let mut f = File::open(config_file).unwrap();
         let mut config_file_src = String::new();
         f.read_to_string(&mut config_file_src).unwrap();
@@ -268,9 +1465,10 @@ mod tests {
         assert_eq!(synced_file.exists(), false);
 
         add(
-            file_to_sync.to_str().unwrap(),
-            &homedir.path().to_str().unwrap(),
-            &sync_dir.path().to_str().unwrap(),
+            &[&file_to_sync],
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
         ).unwrap();
 
         assert_eq!(synced_file.exists(), true);
@@ -300,14 +1498,12 @@ mod tests {
             assert_eq!(file.exists(), false);
         }
 
-        add_files(
-            &files_to_sync
-                .iter()
-                .map(|f| f.to_str().unwrap())
-                .collect::<Vec<_>>(),
-            homedir.path().to_str().unwrap(),
-            sync_dir.path().to_str().unwrap(),
-        );
+        add(
+            &files_to_sync,
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
+        ).unwrap();
 
         // checks file are synced
         for (idx, path) in synced_files.iter().enumerate() {
@@ -322,6 +1518,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn add_creates_relative_symlink_when_asked() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let file_to_sync = homedir.path().join(".config/nvim/init.vim");
+        fs::create_dir_all(homedir.path().join(".config/nvim")).unwrap();
+        File::create(&file_to_sync).unwrap();
+        let options = Options {
+            relative_links: true,
+            ..Options::default()
+        };
+
+        add(
+            &[&file_to_sync],
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &options,
+        ).unwrap();
+
+        let sync_dir_name = sync_dir.path().file_name().unwrap();
+        assert_eq!(
+            fs::read_link(&file_to_sync).unwrap(),
+            Path::new("../..")
+                .join(sync_dir_name)
+                .join(".config/nvim/init.vim")
+        );
+        assert!(file_to_sync.exists());
+
+        remove(
+            &[&file_to_sync],
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &options,
+        ).unwrap();
+
+        assert!(fs::symlink_metadata(&file_to_sync).unwrap().file_type().is_file());
+        assert!(!sync_dir.path().join(".config/nvim/init.vim").exists());
+    }
+
+    #[test]
+    fn apply_with_relative_links_plans_nothing_when_applied_already() {
+        let homedir = TempDir::new("user1").unwrap();
+        let home = Home::new(homedir.path());
+        let sync_dir = SyncDir::new(homedir.path().join("dot-files"));
+        fs::create_dir_all(sync_dir.path().join(".config/nvim")).unwrap();
+        File::create(sync_dir.path().join(".config/nvim").join(LINKED_DIR_MARKER)).unwrap();
+        File::create(sync_dir.path().join(".config/vimrc")).unwrap();
+        let options = Options {
+            relative_links: true,
+            ..Options::default()
+        };
+
+        apply(&home, &sync_dir, &options).unwrap();
+        let backup = Backup::new(home.backups_dir(), home.path());
+        let mut report = Report::new("apply");
+        let plans = plan_apply(&home, slice::from_ref(&sync_dir), &options, &backup, &mut report).unwrap();
+
+        assert!(fs::read_link(homedir.path().join(".config/nvim")).unwrap().is_relative());
+        assert!(fs::read_link(homedir.path().join(".config/vimrc")).unwrap().is_relative());
+        assert!(plans.iter().all(|plan| plan.is_empty()), "{:?}", plans);
+    }
+
     #[test]
     fn added_file_is_removed_ok() {
         let homedir = TempDir::new("user1").unwrap();
@@ -338,9 +1596,14 @@ mod tests {
             true
         );
 
-        let result = remove(&home_file.to_str().unwrap()).unwrap();
+        let result = remove(
+            &[&home_file],
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
+        ).unwrap();
 
-        assert_eq!(result, ());
+        assert_eq!(result.error().is_none(), true);
         assert_eq!(
             fs::symlink_metadata(&home_file)
                 .unwrap()
@@ -351,6 +1614,79 @@ mod tests {
         assert_eq!(Path::new(&sync_file).exists(), false);
     }
 
+    #[test]
+    fn remove_refuses_symlinks_pointing_outside_sync_dir_unless_forced() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let other_dir = TempDir::new_in(homedir.path(), "other").unwrap();
+        fs::create_dir(homedir.path().join(".config")).unwrap();
+        File::create(sync_dir.path().join(".vimrc")).unwrap();
+        File::create(other_dir.path().join(".bashrc")).unwrap();
+        let sync_dir_name = sync_dir.path().file_name().unwrap();
+        let managed = homedir.path().join(".config/.vimrc");
+        unix_fs::symlink(Path::new("..").join(sync_dir_name).join(".vimrc"), &managed).unwrap();
+        let foreign = homedir.path().join(".config/.bashrc");
+        unix_fs::symlink(other_dir.path().join(".bashrc"), &foreign).unwrap();
+        let home = Home::new(homedir.path());
+        let sync = SyncDir::new(sync_dir.path());
+        let is_symlink = |p: &Path| fs::symlink_metadata(p).unwrap().file_type().is_symlink();
+
+        let report = remove(&[&foreign], &home, &sync, &Options::default()).unwrap();
+        assert_eq!(report.error().unwrap().exit_code(), 9);
+        assert!(is_symlink(&foreign));
+
+        remove(&[homedir.path().join(".config")], &home, &sync, &Options::default()).unwrap();
+        assert!(!is_symlink(&managed));
+        assert!(!sync_dir.path().join(".vimrc").exists());
+        assert!(is_symlink(&foreign));
+
+        let force = Options {
+            force: true,
+            ..Options::default()
+        };
+        remove(&[&foreign], &home, &sync, &force).unwrap();
+        assert!(!is_symlink(&foreign));
+    }
+
+    #[test]
+    fn remove_accepts_sync_dir_paths_patterns_and_copies_back() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let subdir = sync_dir.path().join("homedir");
+        fs::create_dir_all(subdir.join(".config")).unwrap();
+        for name in &[".bashrc", ".vimrc", ".config/a.conf", ".config/b.conf"] {
+            File::create(subdir.join(name)).unwrap().write_all(name.as_bytes()).unwrap();
+        }
+        let home = Home::new(homedir.path());
+        let sync = SyncDir::new(sync_dir.path());
+        apply(&home, &sync.subdir("homedir"), &Options::default()).unwrap();
+        let is_file = |name: &str| {
+            fs::symlink_metadata(homedir.path().join(name)).unwrap().file_type().is_file()
+        };
+        let copy_back = Options {
+            copy_back: true,
+            ..Options::default()
+        };
+
+        let pattern = homedir.path().join(".config/*.conf");
+        let report = remove(&[&pattern], &home, &sync, &copy_back).unwrap();
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert!(is_file(".config/a.conf") && is_file(".config/b.conf"));
+        assert_eq!(fs::read(homedir.path().join(".config/a.conf")).unwrap(), b".config/a.conf");
+        assert!(subdir.join(".config/a.conf").exists());
+
+        remove(&[subdir.join(".bashrc")], &home, &sync, &Options::default()).unwrap();
+        assert!(is_file(".bashrc"));
+        assert!(!subdir.join(".bashrc").exists());
+        assert!(!is_file(".vimrc"));
+
+        remove(&[&subdir], &home, &sync, &Options::default()).unwrap();
+        assert!(is_file(".vimrc"));
+
+        let report = remove(&[&pattern], &home, &sync, &Options::default()).unwrap();
+        assert_eq!(report.error().unwrap().exit_code(), 3);
+    }
+
     #[test]
     fn added_files_are_removed_correctly() {
         let homedir = TempDir::new("user1").unwrap();
@@ -381,10 +1717,12 @@ mod tests {
             );
         }
 
-        remove_files(&files_to_restore
-            .iter()
-            .map(|f| f.to_str().unwrap())
-            .collect::<Vec<_>>());
+        remove(
+            &files_to_restore,
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
+        ).unwrap();
 
         for file in files_to_restore {
             // checks that files are regular files
@@ -405,13 +1743,12 @@ mod tests {
         assert_eq!(user_file.exists(), false);
 
         let result = apply(
-            &sync_dir.path().to_str().unwrap(),
-            &sync_dir.path().to_str().unwrap(),
-            &homedir.path().to_str().unwrap(),
-            &vec![],
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
         ).unwrap();
 
-        assert_eq!(result, ());
+        assert_eq!(result.done.len(), 1);
         assert_eq!(
             fs::symlink_metadata(&user_file)
                 .unwrap()
@@ -435,13 +1772,12 @@ mod tests {
         );
 
         let result = apply(
-            &sync_dir.path().to_str().unwrap(),
-            &sync_dir.path().to_str().unwrap(),
-            &homedir.path().to_str().unwrap(),
-            &vec![],
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
         ).unwrap();
 
-        assert_eq!(result, ());
+        assert_eq!(result.done.len(), 2);
         assert_eq!(
             fs::symlink_metadata(&user_file)
                 .unwrap()
@@ -450,4 +1786,572 @@ mod tests {
             true
         );
     }
+
+    #[test]
+    fn apply_layers_takes_file_from_the_last_layer() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        for &(layer, name) in &[
+            ("common", ".bashrc"),
+            ("common", ".vimrc"),
+            ("laptop", ".vimrc"),
+            ("laptop", ".xinitrc"),
+        ] {
+            fs::create_dir_all(sync_dir.path().join(layer)).unwrap();
+            File::create(sync_dir.path().join(layer).join(name)).unwrap();
+        }
+        let sync_dir = SyncDir::new(sync_dir.path());
+        let layers = vec![sync_dir.subdir("common"), sync_dir.subdir("laptop")];
+
+        apply_layers(&Home::new(homedir.path()), &layers, &Options::default()).unwrap();
+
+        for &(layer, name) in &[
+            ("common", ".bashrc"),
+            ("laptop", ".vimrc"),
+            ("laptop", ".xinitrc"),
+        ] {
+            assert_eq!(
+                fs::read_link(homedir.path().join(name)).unwrap(),
+                sync_dir.path().join(layer).join(name)
+            );
+        }
+    }
+
+    #[test]
+    fn apply_adopts_existing_home_files_when_asked() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        File::create(sync_dir.path().join(".vimrc")).unwrap().write_all(b"repo").unwrap();
+        File::create(sync_dir.path().join(".bashrc")).unwrap().write_all(b"repo").unwrap();
+        let user_file = homedir.path().join(".vimrc");
+        File::create(&user_file).unwrap().write_all(b"machine").unwrap();
+        let home = Home::new(homedir.path());
+        let adopt = Options {
+            adopt: true,
+            ..Options::default()
+        };
+
+        let mut report = apply(&home, &SyncDir::new(sync_dir.path()), &adopt).unwrap();
+        report.commit().unwrap();
+
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert_eq!(fs::read_link(&user_file).unwrap(), sync_dir.path().join(".vimrc"));
+        assert_eq!(fs::read(sync_dir.path().join(".vimrc")).unwrap(), b"machine");
+        assert_eq!(fs::read(homedir.path().join(".bashrc")).unwrap(), b"repo");
+        assert_eq!(fs::read_dir(sync_dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn committed_reports_leave_no_replaced_files_behind() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        fs::write(sync_dir.path().join(".bashrc"), b"repo").unwrap();
+        fs::write(homedir.path().join(".bashrc"), b"machine").unwrap();
+        fs::write(sync_dir.path().join(".gitconfig"), b"v1").unwrap();
+        let home = Home::new(homedir.path());
+        let layers = [SyncDir::new(sync_dir.path())];
+        let options = Options {
+            copy: vec![".gitconfig".to_owned()],
+            on_conflict: ConflictPolicy::Overwrite,
+            ..Options::default()
+        };
+        let stashed = |dir: &Path| {
+            fs::read_dir(dir)
+                .unwrap()
+                .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().contains(".ff-"))
+                .count()
+        };
+
+        let mut report = apply_layers(&home, &layers, &options).unwrap();
+        assert_eq!(stashed(homedir.path()), 1);
+        report.commit().unwrap();
+        fs::write(homedir.path().join(".gitconfig"), b"v2").unwrap();
+        let mut report = pull(&home, &layers, &options).unwrap();
+        assert_eq!(stashed(sync_dir.path()), 1);
+        report.commit().unwrap();
+
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert_eq!(fs::read(sync_dir.path().join(".gitconfig")).unwrap(), b"v2");
+        assert_eq!(stashed(homedir.path()), 0);
+        assert_eq!(stashed(sync_dir.path()), 0);
+    }
+
+    #[test]
+    fn secrets_are_encrypted_by_add_and_decrypted_privately_by_apply() {
+        use std::os::unix::fs::PermissionsExt;
+        use status::status;
+
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let other_home = TempDir::new("user2").unwrap();
+        let user_file = homedir.path().join(".netrc");
+        File::create(&user_file).unwrap().write_all(b"password").unwrap();
+        let layers = [SyncDir::new(sync_dir.path())];
+        let options = Options {
+            encrypt: true,
+            passphrase: Some(Passphrase::new("secret")),
+            ..Options::default()
+        };
+
+        let report = add(&[&user_file], &Home::new(homedir.path()), &layers[0], &options).unwrap();
+        let statuses = status(&Home::new(homedir.path()), &layers, &options).unwrap();
+        let other = Home::new(other_home.path());
+        let applied = apply_layers(&other, &layers, &options).unwrap();
+        let other_file = other_home.path().join(".netrc");
+
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        let sync_file = sync_dir.path().join(".netrc.age");
+        assert!(!fs::read_to_string(&sync_file).unwrap().contains("password"));
+        assert_eq!(fs::read(&user_file).unwrap(), b"password");
+        assert_eq!(statuses[0].state, FileState::Decrypted);
+        assert_eq!(applied.error().map(|e| e.to_string()), None);
+        assert_eq!(fs::read(&other_file).unwrap(), b"password");
+        let mode = fs::metadata(&other_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(status(&other, &layers, &options).unwrap()[0].state, FileState::Decrypted);
+        fs::write(&other_file, b"changed").unwrap();
+        assert_eq!(status(&other, &layers, &options).unwrap()[0].state, FileState::ModifiedInHome);
+
+        fs::set_permissions(&user_file, fs::Permissions::from_mode(0o644)).unwrap();
+        apply_layers(&Home::new(homedir.path()), &layers, &options).unwrap();
+        assert_eq!(fs::read(&user_file).unwrap(), b"password");
+        assert_eq!(fs::metadata(&user_file).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn permissions_are_recorded_by_add_and_restored_by_apply() {
+        use history::History;
+        use std::os::unix::fs::PermissionsExt;
+        use status::mode_drift;
+
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let other_home = TempDir::new("user2").unwrap();
+        let set_mode = |path: &Path, mode| fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+        fs::create_dir(homedir.path().join(".ssh")).unwrap();
+        set_mode(&homedir.path().join(".ssh"), 0o700);
+        File::create(homedir.path().join(".ssh/config")).unwrap();
+        set_mode(&homedir.path().join(".ssh/config"), 0o600);
+        let layers = [SyncDir::new(sync_dir.path())];
+
+        add(&[homedir.path().join(".ssh")], &Home::new(homedir.path()), &layers[0], &Options::default()).unwrap();
+        // fresh clone keeps only executable bit
+        set_mode(&sync_dir.path().join(".ssh/config"), 0o644);
+        let other = Home::new(other_home.path());
+        let mut report = apply_layers(&other, &layers, &Options::default()).unwrap();
+        History::new(other.history_dir())
+            .record("apply", mem::take(&mut report.done))
+            .unwrap();
+
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert_eq!(mode_of(&other_home.path().join(".ssh")), Some(0o700));
+        assert!(!other_home.path().join(MODES_FILE).exists());
+        assert_eq!(mode_of(&sync_dir.path().join(".ssh/config")), Some(0o600));
+        assert_eq!(mode_drift(&other, &layers, &Options::default()).unwrap(), vec![]);
+        set_mode(&other_home.path().join(".ssh"), 0o755);
+        assert_eq!(
+            mode_drift(&other, &layers, &Options::default()).unwrap(),
+            vec![ModeDrift {
+                path: other_home.path().join(".ssh"),
+                recorded: 0o700,
+                actual: 0o755,
+            }]
+        );
+        History::new(other.history_dir()).undo_last(false).unwrap();
+        assert_eq!(mode_of(&sync_dir.path().join(".ssh/config")), Some(0o644));
+    }
+
+    #[test]
+    fn apply_renders_templates_and_status_tells_if_they_are_stale() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        File::create(sync_dir.path().join(".gitconfig.tmpl"))
+            .unwrap()
+            .write_all(b"{% if hostname == \"work\" %}\nemail = {{ email }}\n{% endif %}\n")
+            .unwrap();
+        let home = Home::new(homedir.path());
+        let layers = [SyncDir::new(sync_dir.path())];
+        let mut options = Options::default();
+        options.vars.set("hostname", "work");
+        options.vars.set("email", "joe@example.com");
+        let user_file = homedir.path().join(".gitconfig");
+
+        let report = apply_layers(&home, &layers, &options).unwrap();
+
+        assert_eq!(report.error().is_none(), true);
+        assert_eq!(fs::read(&user_file).unwrap(), b"email = joe@example.com\n");
+        let statuses = ::status::status(&home, &layers, &options).unwrap();
+        assert_eq!(statuses[0].state, ::status::FileState::Rendered);
+
+        options.vars.set("hostname", "laptop");
+        let statuses = ::status::status(&home, &layers, &options).unwrap();
+        assert_eq!(statuses[0].state, ::status::FileState::Stale);
+        options.on_conflict = ConflictPolicy::Overwrite;
+        apply_layers(&home, &layers, &options).unwrap();
+        assert_eq!(fs::read(&user_file).unwrap(), b"");
+    }
+
+    #[test]
+    fn copies_are_pulled_and_updated_by_side_which_was_modified() {
+        use std::os::unix::fs::PermissionsExt;
+        use status::{status, FileState};
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let sync_file = sync_dir.path().join(".ssh/config");
+        fs::create_dir_all(sync_file.parent().unwrap()).unwrap();
+        fs::write(&sync_file, b"v1").unwrap();
+        let home = Home::new(homedir.path());
+        let layers = [SyncDir::new(sync_dir.path())];
+        let options = Options {
+            copy: vec![".ssh/".to_owned()],
+            on_conflict: ConflictPolicy::Skip,
+            ..Options::default()
+        };
+        let user_file = homedir.path().join(".ssh/config");
+        let state = || status(&home, &layers, &options).unwrap()[0].state.clone();
+
+        apply_layers(&home, &layers, &options).unwrap();
+        assert_eq!(fs::symlink_metadata(&user_file).unwrap().is_file(), true);
+        assert_eq!(state(), FileState::Copied);
+
+        fs::write(&user_file, b"v2").unwrap();
+        fs::set_permissions(&user_file, fs::Permissions::from_mode(0o750)).unwrap();
+        assert_eq!(state(), FileState::ModifiedInHome);
+        let mut report = pull(&home, &layers, &options).unwrap();
+        report.commit().unwrap();
+        assert_eq!(fs::read(&sync_file).unwrap(), b"v2");
+        assert_eq!(fs::metadata(&sync_file).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(state(), FileState::Copied);
+
+        fs::write(&sync_file, b"v3").unwrap();
+        assert_eq!(state(), FileState::ModifiedInSyncDir);
+        apply_layers(&home, &layers, &options).unwrap();
+        assert_eq!(fs::read(&user_file).unwrap(), b"v3");
+
+        fs::write(&sync_file, b"v4").unwrap();
+        fs::write(&user_file, b"v5").unwrap();
+        assert_eq!(state(), FileState::ModifiedInBoth);
+        let report = pull(&home, &layers, &options).unwrap();
+        assert_eq!(report.records[0].result, Outcome::Skipped);
+    }
+
+    #[test]
+    fn dirs_are_added_applied_and_removed_in_both_layouts() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        for name in &[".config/nvim/init.vim", ".config/nvim/lua/a.lua", ".vim/vimrc"] {
+            let path = homedir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let home = Home::new(homedir.path());
+        let sync = SyncDir::new(sync_dir.path());
+        let nvim = homedir.path().join(".config/nvim");
+        let vim = homedir.path().join(".vim");
+
+        add(&[&nvim], &home, &sync, &Options::default()).unwrap();
+        let link_dirs = Options {
+            link_dirs: true,
+            ..Options::default()
+        };
+        add(&[&vim], &home, &sync, &link_dirs).unwrap();
+
+        let is_symlink = |p: &Path| fs::symlink_metadata(p).unwrap().file_type().is_symlink();
+        assert!(!is_symlink(&nvim));
+        assert!(is_symlink(&nvim.join("lua/a.lua")));
+        assert!(is_symlink(&vim));
+        assert!(is_linked_dir(&sync_dir.path().join(".vim")));
+
+        fs::remove_file(&vim).unwrap();
+        let report = apply(&home, &sync, &Options::default()).unwrap();
+        assert_eq!(report.records.len(), 1);
+        assert_eq!(fs::read_link(&vim).unwrap(), sync_dir.path().join(".vim"));
+
+        remove(&[&nvim, &vim], &home, &sync, &Options::default()).unwrap();
+        for name in &[".config/nvim/init.vim", ".config/nvim/lua/a.lua", ".vim/vimrc"] {
+            assert!(!is_symlink(&homedir.path().join(name)));
+        }
+        assert!(!is_symlink(&vim));
+        assert!(!vim.join(LINKED_DIR_MARKER).exists());
+        assert!(!sync_dir.path().join(".vim").exists());
+    }
+
+    #[test]
+    fn files_with_non_utf8_names_are_added_applied_and_rendered() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::ffi::OsStr;
+        use status::{status, FileState};
+
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let user_file = homedir.path().join(OsStr::from_bytes(b".caf\xe9rc"));
+        File::create(&user_file).unwrap();
+        let template = sync_dir.path().join(OsStr::from_bytes(b"\xff\xfe.tmpl"));
+        File::create(&template).unwrap().write_all(b"{{ os }}").unwrap();
+        let home = Home::new(homedir.path());
+        let sync = SyncDir::new(sync_dir.path());
+        let mut options = Options::default();
+        options.vars.set("os", "linux");
+
+        add(&[&user_file], &home, &sync, &options).unwrap();
+        fs::remove_file(&user_file).unwrap();
+        let report = apply(&home, &sync, &options).unwrap();
+
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert_eq!(
+            fs::read_link(&user_file).unwrap(),
+            sync_dir.path().join(OsStr::from_bytes(b".caf\xe9rc"))
+        );
+        let rendered = homedir.path().join(OsStr::from_bytes(b"\xff\xfe"));
+        assert_eq!(fs::read(rendered).unwrap(), b"linux");
+        let states = status(&home, &[sync], &options)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.state)
+            .collect::<Vec<_>>();
+        assert_eq!(states, vec![FileState::Linked, FileState::Rendered]);
+    }
+
+    #[test]
+    fn plan_add_lists_move_and_symlink() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let file_to_sync = homedir.path().join(".config/nvim/init.vim");
+        let synced_file = sync_dir.path().join(".config/nvim/init.vim");
+
+        let plan = plan_add(
+            &file_to_sync,
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
+        ).unwrap();
+
+        let operations = plan.steps()
+            .iter()
+            .map(|s| s.operation.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            operations,
+            vec![
+                Operation::CreateDir(sync_dir.path().join(".config/nvim")),
+                Operation::Move {
+                    from: file_to_sync.clone(),
+                    to: synced_file.clone(),
+                },
+                Operation::Symlink {
+                    target: synced_file,
+                    link: file_to_sync,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_dry_run_changes_nothing() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let synced_file = sync_dir.path().join(".vimrc");
+        File::create(&synced_file).unwrap();
+        let user_file = homedir.path().join(".vimrc");
+        File::create(&user_file).unwrap();
+
+        apply(
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options {
+                dry_run: true,
+                ..Options::default()
+            },
+        ).unwrap();
+
+        assert!(fs::symlink_metadata(&user_file).unwrap().file_type().is_file());
+    }
+
+    #[test]
+    fn apply_moves_replaced_file_to_backup() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        let backups_dir = homedir.path().join(".ff/backups");
+        fs::create_dir_all(sync_dir.path().join(".config")).unwrap();
+        File::create(sync_dir.path().join(".config/foo")).unwrap();
+        fs::create_dir_all(homedir.path().join(".config")).unwrap();
+        let user_file = homedir.path().join(".config/foo");
+        File::create(&user_file)
+            .unwrap()
+            .write_all(b"local changes")
+            .unwrap();
+
+        apply(
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options::default(),
+        ).unwrap();
+
+        assert!(fs::symlink_metadata(&user_file).unwrap().file_type().is_symlink());
+        let name = list_backups(&backups_dir).pop().unwrap();
+        let mut backed_up = String::new();
+        File::open(backups_dir.join(&name).join(".config/foo"))
+            .unwrap()
+            .read_to_string(&mut backed_up)
+            .unwrap();
+        assert_eq!(backed_up, "local changes");
+
+        restore_backup(&backups_dir, &name, homedir.path(), false).unwrap();
+
+        assert!(fs::symlink_metadata(&user_file).unwrap().file_type().is_file());
+        assert!(!backups_dir.join(&name).exists());
+    }
+
+    #[test]
+    fn apply_skips_conflicting_file_when_asked() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        File::create(sync_dir.path().join(".vimrc"))
+            .unwrap()
+            .write_all(b"set nu")
+            .unwrap();
+        File::create(sync_dir.path().join(".bashrc"))
+            .unwrap()
+            .write_all(b"same")
+            .unwrap();
+        let conflicting_file = homedir.path().join(".vimrc");
+        File::create(&conflicting_file)
+            .unwrap()
+            .write_all(b"set nonu")
+            .unwrap();
+        let same_file = homedir.path().join(".bashrc");
+        File::create(&same_file)
+            .unwrap()
+            .write_all(b"same")
+            .unwrap();
+
+        apply(
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options {
+                on_conflict: ConflictPolicy::Skip,
+                ..Options::default()
+            },
+        ).unwrap();
+
+        assert!(fs::symlink_metadata(&conflicting_file).unwrap().file_type().is_file());
+        assert!(fs::symlink_metadata(&same_file).unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn dry_run_apply_does_not_ask_about_conflicts() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        fs::write(sync_dir.path().join(".vimrc"), "set nu").unwrap();
+        let conflicting_file = homedir.path().join(".vimrc");
+        fs::write(&conflicting_file, "set nonu").unwrap();
+
+        let report = apply(
+            &Home::new(homedir.path()),
+            &SyncDir::new(sync_dir.path()),
+            &Options {
+                dry_run: true,
+                on_conflict: ConflictPolicy::Interactive,
+                ..Options::default()
+            },
+        ).unwrap();
+
+        assert_eq!(report.records[0].result, Outcome::Planned);
+        assert!(report.records[0].message.contains("conflict: would ask"));
+        assert_eq!(fs::read_to_string(&conflicting_file).unwrap(), "set nonu");
+
+        fs::create_dir_all(sync_dir.path().join(".vim")).unwrap();
+        File::create(sync_dir.path().join(".vim").join(LINKED_DIR_MARKER)).unwrap();
+        fs::create_dir(homedir.path().join(".vim")).unwrap();
+        let plan = plan_link_dir(
+            &sync_dir.path().join(".vim"),
+            &SyncDir::new(sync_dir.path()),
+            &Home::new(homedir.path()),
+            &Options {
+                dry_run: true,
+                on_conflict: ConflictPolicy::Interactive,
+                ..Options::default()
+            },
+            &Backup::new(homedir.path().join(".ff/backups"), homedir.path()),
+        ).unwrap();
+        assert!(plan.skipped().unwrap().contains("conflict: would ask"));
+    }
+
+    #[test]
+    fn walk_sync_dir_returns_error_instead_of_skipping_unreadable_entry() {
+        let homedir = TempDir::new("user1").unwrap();
+        let missing = SyncDir::new(homedir.path().join("dot-files"));
+
+        let err = walk_sync_dir(&missing, &[]).unwrap_err();
+
+        assert!(matches!(err, Error::Io { .. }));
+        assert!(err.to_string().contains("dot-files"), "{}", err);
+    }
+
+    #[test]
+    fn swap_path_bases_maps_whole_components_of_leading_base_only() {
+        let home = Path::new("/home/joe");
+        let sync_dir = Path::new("/home/joe/dot-files");
+
+        assert_eq!(
+            swap_path_bases(Path::new("/home/joe/.config/home/joe/x"), home, sync_dir),
+            Some(PathBuf::from("/home/joe/dot-files/.config/home/joe/x"))
+        );
+        assert_eq!(swap_path_bases(Path::new("/home/joey/.bashrc"), home, sync_dir), None);
+        assert_eq!(swap_path_bases(Path::new("/home/jo/.bashrc"), home, sync_dir), None);
+        assert_eq!(swap_path_bases(home, home, sync_dir), Some(sync_dir.to_path_buf()));
+    }
+
+    /// Strategy giving names of files (any bytes but `/` and NUL, so also non-UTF-8 ones)
+    fn file_name() -> impl proptest::strategy::Strategy<Value = OsString> {
+        use proptest::prelude::*;
+        use std::os::unix::ffi::OsStringExt;
+
+        proptest::collection::vec(1u8.., 1..8)
+            .prop_filter("not a file name", |b| {
+                !b.contains(&b'/') && b.as_slice() != b"." && b.as_slice() != b".."
+            })
+            .prop_map(OsString::from_vec)
+    }
+
+    /// Strategy giving paths made of 0 to `max` `file_name`s
+    fn rel_path(max: usize) -> impl proptest::strategy::Strategy<Value = PathBuf> {
+        use proptest::prelude::*;
+
+        proptest::collection::vec(file_name(), 0..max)
+            .prop_map(|names| names.iter().collect::<PathBuf>())
+    }
+
+    proptest! {
+        #[test]
+        fn swap_path_bases_moves_path_between_bases_and_back(
+            old_base in rel_path(4),
+            new_base in rel_path(4),
+            rel in rel_path(4),
+        ) {
+            let old_base = Path::new("/").join(old_base);
+            let new_base = Path::new("/").join(new_base);
+            let path = old_base.join(&rel);
+
+            let swapped = swap_path_bases(&path, &old_base, &new_base);
+            prop_assert_eq!(swapped.as_ref(), Some(&new_base.join(&rel)));
+            let swapped = swapped.unwrap();
+            prop_assert_eq!(swap_path_bases(&swapped, &new_base, &old_base), Some(path));
+        }
+
+        #[test]
+        fn swap_path_bases_rejects_paths_outside_base(
+            base in rel_path(3),
+            name in file_name(),
+            suffix in file_name(),
+            rel in rel_path(3),
+        ) {
+            let base = Path::new("/").join(base);
+            let mut sibling = name.clone();
+            sibling.push(&suffix);
+            let path = base.join(&sibling).join(&rel);
+
+            prop_assert_eq!(swap_path_bases(&path, &base.join(&name), Path::new("/x")), None);
+        }
+    }
 }
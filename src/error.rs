@@ -0,0 +1,223 @@
+//! failures reported by `ff` (each one has its own process exit code)
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Failure of `ff` operation
+#[derive(Debug)]
+pub enum Error {
+    /// Filesystem operation failed
+    Io {
+        /// what was being done, e.g. `Can't remove "/home/joe/.vimrc"`
+        context: String,
+        /// underlying error
+        source: io::Error,
+    },
+    /// Home dir can't be found
+    NoHomeDir,
+    /// Config file can't be parsed
+    Config {
+        /// path to config file
+        path: PathBuf,
+        /// what's wrong with it
+        reason: String,
+    },
+    /// Config lacks `sync-dir` (`ff init` wasn't run)
+    NotInitialized {
+        /// path to config file
+        path: PathBuf,
+    },
+    /// Path can't be used, e.g. it's outside of home dir
+    InvalidPath {
+        /// the path
+        path: PathBuf,
+        /// why it can't be used
+        reason: String,
+    },
+    /// Argument value is not accepted, e.g. unknown conflict policy
+    InvalidArgument(String),
+    /// Requested item doesn't exist, e.g. backup
+    NotFound(String),
+    /// Data stored by `ff` can't be parsed, e.g. history entry
+    Corrupted {
+        /// path to the data
+        path: PathBuf,
+        /// what's wrong with it
+        reason: String,
+    },
+    /// Template of sync-dir can't be rendered, e.g. it uses undefined variable
+    Template {
+        /// path to the template
+        path: PathBuf,
+        /// what's wrong with it
+        reason: String,
+    },
+    /// Git command run in sync-dir failed
+    Git {
+        /// arguments of the command
+        command: String,
+        /// what git printed about the failure
+        reason: String,
+    },
+    /// Secret of sync-dir can't be encrypted or decrypted, e.g. passphrase is wrong
+    Secret {
+        /// path to the secret
+        path: PathBuf,
+        /// what's wrong with it
+        reason: String,
+    },
+    /// Ctrl-C was pressed
+    Interrupted,
+    /// Operation failed and changes completed so far were reverted
+    Reverted(Box<Error>),
+    /// Operation failed and changes completed so far couldn't be reverted
+    RevertFailed {
+        /// failure of the operation
+        error: Box<Error>,
+        /// failure of reverting
+        revert_error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Gives `Error::Io` describing failure of `context`
+    pub fn io<T: Into<String>>(context: T, source: io::Error) -> Error {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Gives `Error::InvalidPath`
+    pub fn invalid_path<P: Into<PathBuf>, T: Into<String>>(path: P, reason: T) -> Error {
+        Error::InvalidPath {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns process exit code for the error
+    ///
+    /// * `2` - invalid argument
+    /// * `3` - file or other item not found
+    /// * `4` - permission denied
+    /// * `5` - other filesystem failure
+    /// * `6` - `ff init` wasn't run
+    /// * `7` - broken config file
+    /// * `8` - home dir not found
+    /// * `9` - invalid path
+    /// * `10` - broken data stored by `ff`
+    /// * `11` - changes couldn't be reverted
+    /// * `12` - template can't be rendered
+    /// * `13` - git command failed
+    /// * `14` - secret can't be encrypted or decrypted
+    /// * `130` - interrupted
+    ///
+    /// Reverted failure keeps the code of its cause
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::InvalidArgument(_) => 2,
+            Error::NotFound(_) => 3,
+            Error::Io { ref source, .. } => match source.kind() {
+                io::ErrorKind::NotFound => 3,
+                io::ErrorKind::PermissionDenied => 4,
+                _ => 5,
+            },
+            Error::NotInitialized { .. } => 6,
+            Error::Config { .. } => 7,
+            Error::NoHomeDir => 8,
+            Error::InvalidPath { .. } => 9,
+            Error::Corrupted { .. } => 10,
+            Error::RevertFailed { .. } => 11,
+            Error::Template { .. } => 12,
+            Error::Git { .. } => 13,
+            Error::Secret { .. } => 14,
+            Error::Interrupted => 130,
+            Error::Reverted(ref error) => error.exit_code(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io {
+                ref context,
+                ref source,
+            } => write!(f, "{} ({})", context, source),
+            Error::NoHomeDir => write!(f, "Can't find home dir"),
+            Error::Config {
+                ref path,
+                ref reason,
+            } => write!(f, "Can't load config file: {} ({})", path.display(), reason),
+            Error::NotInitialized { ref path } => write!(
+                f,
+                "Can't find 'sync-dir' value in config file: {}\n\
+                 Did you run: 'ff init' on your sync-dir?",
+                path.display()
+            ),
+            Error::InvalidPath {
+                ref path,
+                ref reason,
+            } => write!(f, "{}: {:?}", reason, path),
+            Error::InvalidArgument(ref msg) | Error::NotFound(ref msg) => write!(f, "{}", msg),
+            Error::Corrupted {
+                ref path,
+                ref reason,
+            } => write!(f, "Can't parse {:?} ({})", path, reason),
+            Error::Template {
+                ref path,
+                ref reason,
+            } => write!(f, "Can't render template {:?} ({})", path, reason),
+            Error::Git {
+                ref command,
+                ref reason,
+            } => write!(f, "Command 'git {}' failed ({})", command, reason),
+            Error::Secret {
+                ref path,
+                ref reason,
+            } => write!(f, "Can't use secret {:?} ({})", path, reason),
+            Error::Interrupted => write!(f, "Interrupted (Ctrl-C)"),
+            Error::Reverted(ref error) => write!(f, "{} (all changes reverted)", error),
+            Error::RevertFailed {
+                ref error,
+                ref revert_error,
+            } => write!(
+                f,
+                "{} (can't revert: {} - clean it MANUALLY)",
+                error, revert_error
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io { ref source, .. } => Some(source),
+            Error::Reverted(ref error) | Error::RevertFailed { ref error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn exit_code_depends_on_io_error_kind() {
+        let missing = Error::io("Can't remove", io::Error::from(io::ErrorKind::NotFound));
+        let denied = Error::io(
+            "Can't remove",
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        );
+
+        assert_eq!(missing.exit_code(), 3);
+        assert_eq!(denied.exit_code(), 4);
+        assert_eq!(Error::Reverted(Box::new(denied)).exit_code(), 4);
+        assert!(missing.source().is_some());
+    }
+}
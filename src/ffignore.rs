@@ -0,0 +1,153 @@
+//! sync-dir paths skipped by ff, listed in `.ffignore` files (gitignore syntax)
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use error::Error;
+use modes::MODES_FILE;
+
+/// Name of file listing ignored paths of its dir (and subdirs)
+pub const IGNORE_FILE: &str = ".ffignore";
+
+/// Paths of sync-dir skipped by `apply`, `status` and other actions walking sync-dir
+///
+/// Patterns of `.ffignore` in a subdir take precedence over those of its parents
+/// (like `.gitignore` files do). Contents of ignored dir are ignored too
+#[derive(Debug)]
+pub struct Ignored {
+    sync_dir: PathBuf,
+    prefixes: Vec<String>,
+    matchers: Vec<Gitignore>,
+}
+
+impl Ignored {
+    /// Gives ignored paths of `sync_dir`
+    ///
+    /// `prefixes` are prefixes of paths relative to `sync_dir` (see: `ignore-when-apply`),
+    /// `.ffignore` files are read by `load_dir`
+    pub fn new(sync_dir: &Path, prefixes: &[String]) -> Ignored {
+        Ignored {
+            sync_dir: sync_dir.to_path_buf(),
+            prefixes: prefixes
+                .iter()
+                .filter(|p| !p.is_empty())
+                .cloned()
+                .collect(),
+            matchers: Vec::new(),
+        }
+    }
+
+    /// Reads `.ffignore` of `dir` (if it exists), it has to be called before checking `dir` contents
+    pub fn load_dir(&mut self, dir: &Path) -> Result<(), Error> {
+        let file = dir.join(IGNORE_FILE);
+        if !file.is_file() {
+            return Ok(());
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&file) {
+            return Err(Error::Corrupted {
+                path: file,
+                reason: e.to_string(),
+            });
+        }
+        let matcher = builder.build().map_err(|e| Error::Corrupted {
+            path: file.clone(),
+            reason: e.to_string(),
+        })?;
+        self.matchers.push(matcher);
+        Ok(())
+    }
+
+    /// Returns `true` if `path` (which is a dir if `is_dir` is set) is ignored
+    ///
+    /// `.ffignore` files and manifest of permissions (see: `ff::modes`) are always ignored
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path.file_name() == Some(IGNORE_FILE.as_ref()) || path == self.sync_dir.join(MODES_FILE) {
+            return true;
+        }
+        if let Ok(rel_path) = path.strip_prefix(&self.sync_dir) {
+            let mut rel_path = rel_path.as_os_str().as_bytes().to_vec();
+            if is_dir {
+                rel_path.push(b'/');
+            }
+            if rel_path != b"/" && self.prefixes.iter().any(|p| rel_path.starts_with(p.as_bytes())) {
+                return true;
+            }
+        }
+        // the deepest `.ffignore` which has an opinion wins
+        for matcher in self.matchers.iter().rev() {
+            let rel_path = match path.strip_prefix(matcher.path()) {
+                Err(_) => continue,
+                Ok(v) => v,
+            };
+            match matcher.matched(rel_path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use tempdir::TempDir;
+
+    use core::*;
+    use paths::*;
+
+    #[test]
+    fn walk_skips_paths_listed_in_ffignore_files() {
+        let sync_dir = TempDir::new("dot-files").unwrap();
+        let files = [
+            "README.md",
+            ".bashrc",
+            "notes.txt",
+            "keep.txt",
+            "build/out",
+            ".config/nvim/init.vim",
+            ".config/nvim/cache/x",
+            ".config/foo/secret.key",
+            ".config/foo/public.key",
+            ".git/HEAD",
+        ];
+        for file in &files {
+            let path = sync_dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        File::create(sync_dir.path().join(".ffignore"))
+            .unwrap()
+            .write_all(b"README.md\n*.txt\n!keep.txt\nbuild/\n**/cache\n*.key\n")
+            .unwrap();
+        File::create(sync_dir.path().join(".config/foo/.ffignore"))
+            .unwrap()
+            .write_all(b"!public.key\n")
+            .unwrap();
+
+        let entries = walk_sync_dir(&SyncDir::new(sync_dir.path()), &[".git/".to_owned()]).unwrap();
+
+        let mut walked = entries
+            .iter()
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.path().strip_prefix(sync_dir.path()).unwrap().to_path_buf())
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        walked.sort();
+        assert_eq!(
+            walked,
+            vec![
+                ".bashrc",
+                ".config/foo/public.key",
+                ".config/nvim/init.vim",
+                "keep.txt",
+            ]
+        );
+    }
+}
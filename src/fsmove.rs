@@ -0,0 +1,204 @@
+//! moves files also between filesystems, where rename fails (e.g. sync-dir on other mount)
+use std::ffi::OsString;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process;
+
+use libc;
+
+use copies::file_hash;
+
+/// Moves `from` (file, dir or symlink) to `to`, replacing `to` like rename does
+///
+/// When `from` and `to` are on different filesystems it's copied (see: `copy_over`)
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    move_with(from, to, |from, to| fs::rename(from, to))
+}
+
+/// Moves `from` to `to` with `rename`, falls back to `copy_over` when it fails with `EXDEV`
+fn move_with<R>(from: &Path, to: &Path, rename: R) -> io::Result<()>
+where
+    R: Fn(&Path, &Path) -> io::Result<()>,
+{
+    match rename(from, to) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) => copy_over(from, to),
+        result => result,
+    }
+}
+
+/// Copies `from` to temporary path next to `to` (flushing it to disk), checks the copy,
+/// renames it to `to` and removes `from`
+///
+/// Permissions, timestamps and (if allowed) ownership are kept. When anything fails
+/// `from` is left untouched and the copy is removed (so `to` is too, unless it was replaced
+/// and `from` got removed only partially, then the copy is the only complete one)
+pub fn copy_over(from: &Path, to: &Path) -> io::Result<()> {
+    copy_over_with(from, to, remove_tree)
+}
+
+/// Does `copy_over` with `from` removed by `remove`
+fn copy_over_with<D>(from: &Path, to: &Path, remove: D) -> io::Result<()>
+where
+    D: Fn(&Path) -> io::Result<()>,
+{
+    let name = to
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path without file name"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".ff-copy-{}", process::id()));
+    let temp = to.with_file_name(temp_name);
+    let copied = copy_tree(from, &temp)
+        .and_then(|_| verify_copy(from, &temp))
+        .and_then(|_| fs::rename(&temp, to));
+    if let Err(e) = copied {
+        let _ = remove_tree(&temp);
+        return Err(e);
+    }
+    if let Some(dir) = to.parent() {
+        sync_dir(dir)?;
+    }
+    if let Err(e) = remove(from) {
+        // failed move shouldn't replace `to` (see: `ff::journal::Journal`)
+        if verify_copy(from, to).is_ok() && fs::rename(to, &temp).is_ok() {
+            let _ = remove_tree(&temp);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Copies `from` (recursively) to `to` which can't exist, each file is flushed to disk
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        return unix_fs::symlink(fs::read_link(from)?, to);
+    }
+    if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        sync_dir(to)?;
+    } else {
+        let mut source = File::open(from)?;
+        let mut copy = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(to)?;
+        io::copy(&mut source, &mut copy)?;
+        copy.sync_all()?;
+    }
+    copy_metadata(&metadata, to)
+}
+
+/// Sets permissions, timestamps and ownership of `path` to those of `metadata`
+fn copy_metadata(metadata: &fs::Metadata, path: &Path) -> io::Result<()> {
+    // set before permissions, which may make it unreadable (e.g. 0200)
+    let times = fs::FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::open(path)?.set_times(times)?;
+    // changing owner is allowed only to root, so it's kept when possible
+    let _ = unix_fs::chown(path, Some(metadata.uid()), Some(metadata.gid()));
+    fs::set_permissions(path, fs::Permissions::from_mode(metadata.permissions().mode()))
+}
+
+/// Returns error unless `copy` has the same type, contents (and dir entries) as `original`
+fn verify_copy(original: &Path, copy: &Path) -> io::Result<()> {
+    let (a, b) = (fs::symlink_metadata(original)?, fs::symlink_metadata(copy)?);
+    let same = if a.file_type().is_symlink() {
+        b.file_type().is_symlink() && fs::read_link(original)? == fs::read_link(copy)?
+    } else if a.is_dir() {
+        let count = fs::read_dir(original)?.count();
+        for entry in fs::read_dir(original)? {
+            let entry = entry?;
+            verify_copy(&entry.path(), &copy.join(entry.file_name()))?;
+        }
+        b.is_dir() && fs::read_dir(copy)?.count() == count
+    } else {
+        let hash = |path| file_hash(path).map_err(|e| io::Error::other(e.to_string()));
+        b.is_file() && a.len() == b.len() && hash(original)? == hash(copy)?
+    };
+    if !same {
+        return Err(io::Error::other(format!(
+            "copy {:?} differs from {:?}",
+            copy, original
+        )));
+    }
+    Ok(())
+}
+
+/// Flushes entries of `dir` to disk
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Removes `path` (dirs with their contents)
+fn remove_tree(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref m) if m.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn move_copies_files_and_dirs_when_rename_crosses_filesystems() {
+        let dir = TempDir::new("ff").unwrap();
+        let exdev = |_: &Path, _: &Path| Err(io::Error::from_raw_os_error(libc::EXDEV));
+        let file = dir.path().join(".bashrc");
+        fs::write(&file, "alias ll='ls -l'").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        fs::create_dir_all(dir.path().join(".config/nvim")).unwrap();
+        fs::write(dir.path().join(".config/nvim/init.vim"), "set nu").unwrap();
+        unix_fs::symlink("nvim/init.vim", dir.path().join(".config/vimrc")).unwrap();
+        fs::create_dir(dir.path().join("sync")).unwrap();
+
+        move_with(&file, &dir.path().join("sync/.bashrc"), exdev).unwrap();
+        move_with(&dir.path().join(".config"), &dir.path().join("sync/.config"), exdev).unwrap();
+
+        let moved = dir.path().join("sync/.bashrc");
+        assert!(!file.exists());
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "alias ll='ls -l'");
+        assert_eq!(fs::metadata(&moved).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::metadata(&moved).unwrap().modified().unwrap(), modified);
+        assert!(!dir.path().join(".config").exists());
+        assert_eq!(fs::read_to_string(dir.path().join("sync/.config/nvim/init.vim")).unwrap(), "set nu");
+        assert_eq!(
+            fs::read_link(dir.path().join("sync/.config/vimrc")).unwrap(),
+            Path::new("nvim/init.vim")
+        );
+        assert_eq!(fs::read_dir(dir.path().join("sync")).unwrap().count(), 2);
+
+        let write_only = dir.path().join(".history");
+        fs::write(&write_only, "ls").unwrap();
+        fs::set_permissions(&write_only, fs::Permissions::from_mode(0o200)).unwrap();
+        move_with(&write_only, &dir.path().join("sync/.history"), exdev).unwrap();
+        let moved_history = fs::metadata(dir.path().join("sync/.history")).unwrap();
+        assert_eq!(moved_history.permissions().mode() & 0o777, 0o200);
+        fs::remove_file(dir.path().join("sync/.history")).unwrap();
+
+        let other = |_: &Path, _: &Path| Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(move_with(&moved, &file, other).is_err());
+        assert!(moved.exists());
+
+        let stuck = |_: &Path| Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(copy_over_with(&moved, &file, stuck).is_err());
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "alias ll='ls -l'");
+        assert!(fs::symlink_metadata(&file).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
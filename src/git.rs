@@ -0,0 +1,476 @@
+//! keeps sync-dir in git repository (using local `git` binary)
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use config::Config;
+use conflict::ConflictPolicy;
+use core::*;
+use error::Error;
+use journal::Done;
+use modes::MODES_FILE;
+use paths::*;
+use report::*;
+
+/// Git work tree containing sync-dir
+#[derive(Debug)]
+pub struct Git {
+    dir: PathBuf,
+}
+
+impl Git {
+    /// Gives work tree containing `dir` (commands are run in `dir`)
+    pub fn new<P: AsRef<Path>>(dir: P) -> Git {
+        Git {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Runs `git` with `args`, fails only if it can't be run
+    fn output<I, S>(&self, args: I) -> Result<Output, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args)
+            .output()
+            .map_err(|e| Error::io("Can't run git", e))
+    }
+
+    /// Runs `git` with `args`, returns its output (fails when it doesn't succeed)
+    fn run<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Vec<u8>, Error> {
+        let output = self.output(args)?;
+        if !output.status.success() {
+            return Err(Error::Git {
+                command: args
+                    .iter()
+                    .map(|a| a.as_ref().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                reason: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+        Ok(output.stdout)
+    }
+
+    /// Returns `true` if `git` can be run and dir is inside work tree
+    pub fn is_repo(&self) -> bool {
+        self.output(["rev-parse", "--is-inside-work-tree"])
+            .map(|output| output.status.success() && output.stdout.starts_with(b"true"))
+            .unwrap_or(false)
+    }
+
+    /// Returns root dir of work tree
+    fn top_level(&self) -> Result<PathBuf, Error> {
+        let output = self.run(&["rev-parse", "--show-toplevel"])?;
+        let line = output.split(|&b| b == b'\n').next().unwrap_or_default();
+        Ok(PathBuf::from(OsStr::from_bytes(line)))
+    }
+
+    /// Stages changes of `paths` (also removed ones) and commits them with `message`
+    /// (other staged changes aren't committed)
+    ///
+    /// Returns `false` when there is nothing to commit
+    pub fn commit(&self, paths: &[PathBuf], message: &str) -> Result<bool, Error> {
+        let (existing, removed): (Vec<_>, Vec<_>) = paths.iter().partition(|p| p.exists());
+        if !existing.is_empty() {
+            let mut args: Vec<&OsStr> = vec!["add".as_ref(), "-A".as_ref(), "--".as_ref()];
+            args.extend(existing.iter().map(|p| p.as_os_str()));
+            self.run(&args)?;
+        }
+        if !removed.is_empty() {
+            let mut args: Vec<&OsStr> = vec![
+                "rm".as_ref(),
+                "-r".as_ref(),
+                "-q".as_ref(),
+                "--cached".as_ref(),
+                "--ignore-unmatch".as_ref(),
+                "--".as_ref(),
+            ];
+            args.extend(removed.iter().map(|p| p.as_os_str()));
+            self.run(&args)?;
+        }
+        // only staged changes of `paths` are committed, those staged by user are left
+        let mut args: Vec<&OsStr> = vec![
+            "diff".as_ref(),
+            "--cached".as_ref(),
+            "--name-only".as_ref(),
+            "-z".as_ref(),
+            "--".as_ref(),
+        ];
+        args.extend(paths.iter().map(|p| p.as_os_str()));
+        let output = self.run(&args)?;
+        let top_level = self.top_level()?;
+        let staged = output
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| top_level.join(OsStr::from_bytes(name)))
+            .collect::<Vec<_>>();
+        if staged.is_empty() {
+            return Ok(false);
+        }
+        let mut args: Vec<&OsStr> = vec![
+            "commit".as_ref(),
+            "-q".as_ref(),
+            "-m".as_ref(),
+            message.as_ref(),
+            "--".as_ref(),
+        ];
+        args.extend(staged.iter().map(|p| p.as_os_str()));
+        self.run(&args)?;
+        Ok(true)
+    }
+
+    /// Clones repository at `url` (or local path) into `dest`
+    pub fn clone<S: AsRef<OsStr>>(url: S, dest: &Path) -> Result<Git, Error> {
+        let url = url.as_ref();
+        let output = Command::new("git")
+            .args(["clone", "-q", "--"].iter())
+            .arg(url)
+            .arg(dest)
+            .output()
+            .map_err(|e| Error::io("Can't run git", e))?;
+        if !output.status.success() {
+            return Err(Error::Git {
+                command: format!("clone {} {}", url.to_string_lossy(), dest.display()),
+                reason: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+        Ok(Git::new(dest))
+    }
+
+    /// Fetches and rebases commits of upstream branch
+    pub fn pull(&self) -> Result<(), Error> {
+        self.run(&["pull", "-q", "--rebase"]).map(|_| ())
+    }
+
+    /// Pushes commits to upstream branch
+    pub fn push(&self) -> Result<(), Error> {
+        self.run(&["push", "-q"]).map(|_| ())
+    }
+
+    /// Returns uncommitted changes of files inside dir, each as status code
+    /// (like `M`, `??`, see: `git status --short`) and path
+    pub fn uncommitted(&self) -> Result<Vec<(String, PathBuf)>, Error> {
+        let top_level = self.top_level()?;
+        let output = self.run(&["status", "--porcelain", "-z", "--", "."])?;
+        let mut changes = Vec::new();
+        let mut entries = output.split(|&b| b == 0).filter(|e| e.len() > 3);
+        while let Some(entry) = entries.next() {
+            let code = String::from_utf8_lossy(&entry[..2]).trim().to_owned();
+            if code.starts_with('R') || code.starts_with('C') {
+                // original path of renamed (copied) file follows
+                entries.next();
+            }
+            changes.push((code, top_level.join(OsStr::from_bytes(&entry[3..]))));
+        }
+        Ok(changes)
+    }
+}
+
+/// Returns paths changed by `done` operations which are inside `dir`
+pub fn changed_paths(done: &[Done], dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for item in done {
+        let changed = match *item {
+            Done::CreatedDirs(_) => vec![],
+            Done::Symlinked(ref path)
+            | Done::Written(ref path)
+            | Done::Removed { ref path, .. }
+            | Done::ModeSet { ref path, .. } => vec![path],
+            Done::Moved {
+                ref from, ref to, ..
+            } => vec![from, to],
+        };
+        for path in changed {
+            if path.starts_with(dir) && !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+    }
+    paths
+}
+
+/// Commits sync-dir changes done by action of `report` (if `dir` is in git work tree)
+///
+/// Commit message lists changed files relative to `dir` (manifest of permissions is committed
+/// with them, see: `ff::modes`). Failure is added to `report`
+pub fn commit_report(dir: &Path, report: &mut Report) {
+    let git = Git::new(dir);
+    let mut paths = changed_paths(&report.done, dir);
+    if paths.is_empty() || !git.is_repo() {
+        return;
+    }
+    if dir.join(MODES_FILE).exists() {
+        paths.push(dir.join(MODES_FILE));
+    }
+    let mut message = format!("ff {}: {} file(s)\n\n", report.action, paths.len());
+    for path in &paths {
+        let rel_path = path.strip_prefix(dir).unwrap_or(path);
+        message.push_str(&format!("{}\n", rel_path.display()));
+    }
+    match git.commit(&paths, &message) {
+        Ok(true) => report.notes.push(format!("committed changes of sync-dir: {:?}", dir)),
+        Ok(false) => {}
+        Err(e) => report.push(dir, Outcome::Failed, "can't commit changes", Some(e)),
+    }
+}
+
+/// Pulls commits to sync-dir `dir`, applies `layers` (see: `ff::core::apply_layers`)
+/// and pushes commits of `dir` back
+///
+/// Push is skipped when apply fails. When `options.dry_run` is set git isn't run
+pub fn sync(dir: &Path, home: &Home, layers: &[SyncDir], options: &Options) -> Result<Report, Error> {
+    let git = Git::new(dir);
+    if !git.is_repo() {
+        return Err(Error::invalid_path(dir, "Sync-dir isn't in git repository"));
+    }
+    let mut report = Report::new("sync");
+    if options.dry_run {
+        report.push(dir, Outcome::Planned, format!("git pull: {:?}", dir), None);
+    } else {
+        git.pull()?;
+        report.push(dir, Outcome::Done, format!("pulled: {:?}", dir), None);
+    }
+    let applied = apply_layers(home, layers, options)?;
+    let failed = applied.error().is_some();
+    report.extend(applied);
+    if options.dry_run {
+        report.push(dir, Outcome::Planned, format!("git push: {:?}", dir), None);
+    } else if failed {
+        report.push(dir, Outcome::Skipped, "push skipped (apply failed)", None);
+    } else {
+        match git.push() {
+            Ok(()) => report.push(dir, Outcome::Done, format!("pushed: {:?}", dir), None),
+            Err(e) => report.push(dir, Outcome::Failed, "can't push", Some(e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Returns dir name `git clone` would give to repository at `url`, e.g. `dot-files`
+/// for `https://example.com/joe/dot-files.git`
+pub fn repo_name(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    let name = url.rsplit(['/', ':'].as_ref()).next().unwrap_or(url);
+    name.trim_end_matches(".git")
+}
+
+/// Sets up new machine: clones repository at `url` into `dest` (see: `repo_name` for default),
+/// makes it sync-dir (see: `ff::core::init`) and applies its `subdir` or `profile` layers
+///
+/// Replaced home-dir files are always backed up (`options.on_conflict` is ignored).
+/// Summary of the apply is the last note of the report
+pub fn bootstrap(
+    url: &str,
+    dest: Option<&Path>,
+    subdir: &str,
+    profile: &str,
+    home: &Home,
+    config: &Config,
+    options: &Options,
+) -> Result<Report, Error> {
+    let dest = match dest {
+        Some(dest) => absolute(dest)?,
+        None => home.path().join(repo_name(url)),
+    };
+    // checked before cloning, so nothing is left behind when `init` would refuse it
+    if !home.contains(&dest) {
+        return Err(Error::invalid_path(dest, "Sync dir should be descendant of home dir"));
+    }
+    if fs::read_dir(&dest).map(|mut d| d.next().is_some()).unwrap_or(false) {
+        return Err(Error::invalid_path(dest, "Destination dir isn't empty"));
+    }
+    let mut report = Report::new("bootstrap");
+    if options.dry_run {
+        report.push(&dest, Outcome::Planned, format!("git clone: {} -> {:?}", url, dest), None);
+        report.push(
+            &dest,
+            Outcome::Planned,
+            format!("set config: sync-dir={} and apply it", dest.display()),
+            None,
+        );
+        return Ok(report);
+    }
+    Git::clone(url, &dest)?;
+    report.push(&dest, Outcome::Done, format!("cloned: {} (to: {:?})", url, dest), None);
+    report.extend(init(&dest, home, config, false)?);
+    let sync_dir = SyncDir::from_config(config)?;
+    let layers = if profile.is_empty() {
+        vec![sync_dir.subdir(subdir)]
+    } else {
+        sync_dir.layers(config, profile)?
+    };
+    // options were read from config before `init` set what's ignored (e.g. `.git/`)
+    let applied = apply_layers(home, &layers, &Options {
+        on_conflict: ConflictPolicy::Backup,
+        to_ignore: config.get_ignored()?,
+        ..options.clone()
+    })?;
+    let summary = format!(
+        "Bootstrapped {:?}: {} file(s) applied, {} skipped, {} failed",
+        sync_dir.path(),
+        applied.count(Outcome::Done),
+        applied.count(Outcome::Skipped),
+        applied.count(Outcome::Failed) + applied.count(Outcome::Reverted),
+    );
+    report.extend(applied);
+    report.notes.push(summary);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backup::list_backups;
+    use std::fs::File;
+    use tempdir::TempDir;
+
+    /// Runs `git` with `args` in `dir` (test repos are set up with it)
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// Gives clone of bare repo (inside `root`) with one commit pushed already
+    fn cloned_repo(root: &Path, name: &str) -> PathBuf {
+        let bare = root.join("remote.git");
+        if !bare.exists() {
+            git(root, &["init", "-q", "--bare", "remote.git"]);
+        }
+        git(root, &["clone", "-q", "remote.git", name]);
+        let clone = root.join(name);
+        git(&clone, &["config", "user.name", "Joe"]);
+        git(&clone, &["config", "user.email", "joe@example.com"]);
+        if !clone.join(".gitignore").exists() {
+            File::create(clone.join(".gitignore")).unwrap();
+            git(&clone, &["add", ".gitignore"]);
+            git(&clone, &["commit", "-q", "-m", "init"]);
+            git(&clone, &["push", "-q", "origin", "HEAD"]);
+        }
+        clone
+    }
+
+    #[test]
+    fn changes_are_committed_and_reported_as_uncommitted_before() {
+        let root = TempDir::new("ff").unwrap();
+        let repo = cloned_repo(root.path(), "dot-files");
+        let git_repo = Git::new(&repo);
+        File::create(repo.join(".bashrc")).unwrap();
+
+        assert!(git_repo.is_repo());
+        assert!(!Git::new(root.path()).is_repo());
+        assert_eq!(
+            git_repo.uncommitted().unwrap(),
+            vec![("??".to_owned(), repo.join(".bashrc"))]
+        );
+
+        fs::write(repo.join(".vimrc"), "set nu").unwrap();
+        git(&repo, &["add", ".vimrc"]);
+        assert!(git_repo.commit(&[repo.join(".bashrc")], "add").unwrap());
+        assert_eq!(git_repo.uncommitted().unwrap(), vec![("A".to_owned(), repo.join(".vimrc"))]);
+        git(&repo, &["rm", "-q", "--cached", ".vimrc"]);
+        fs::remove_file(repo.join(".vimrc")).unwrap();
+        assert!(git_repo.uncommitted().unwrap().is_empty());
+        fs::remove_file(repo.join(".bashrc")).unwrap();
+        assert!(git_repo.commit(&[repo.join(".bashrc"), repo.join(".vimrc")], "rm").unwrap());
+        assert!(!git_repo.commit(&[repo.join(".bashrc")], "rm").unwrap());
+    }
+
+    #[test]
+    fn sync_pulls_applies_and_pushes() {
+        let root = TempDir::new("ff").unwrap();
+        let home = Home::new(root.path());
+        let machine = cloned_repo(root.path(), "dot-files");
+        let other_machine = cloned_repo(root.path(), "other");
+        File::create(other_machine.join(".vimrc")).unwrap();
+        git(&other_machine, &["add", ".vimrc"]);
+        git(&other_machine, &["commit", "-q", "-m", "vimrc"]);
+        git(&other_machine, &["push", "-q"]);
+        File::create(machine.join(".bashrc")).unwrap();
+        let mut report = Report::new("add");
+        report.done.push(Done::Written(machine.join(".bashrc")));
+        commit_report(&machine, &mut report);
+
+        let report = sync(&machine, &home, &[SyncDir::new(&machine)], &Options {
+            to_ignore: vec![".git/".to_owned()],
+            ..Options::default()
+        }).unwrap();
+
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert_eq!(fs::read_link(root.path().join(".vimrc")).unwrap(), machine.join(".vimrc"));
+        assert!(fs::read_link(root.path().join(".bashrc")).is_ok());
+        git(&other_machine, &["pull", "-q", "--rebase"]);
+        assert!(other_machine.join(".bashrc").exists());
+    }
+
+    #[test]
+    fn bootstrap_clones_inits_and_applies_with_backups() {
+        let root = TempDir::new("ff").unwrap();
+        let home = Home::new(root.path());
+        let config = Config::new(root.path().join(".ff/config.ini")).unwrap();
+        let origin = cloned_repo(root.path(), "origin");
+        fs::create_dir(origin.join("homedir")).unwrap();
+        fs::write(origin.join("homedir/.bashrc"), "synced").unwrap();
+        git(&origin, &["add", "homedir"]);
+        git(&origin, &["commit", "-q", "-m", "bashrc"]);
+        git(&origin, &["push", "-q"]);
+        fs::write(root.path().join(".bashrc"), "local").unwrap();
+
+        assert_eq!(repo_name("https://example.com/joe/dot-files.git/"), "dot-files");
+        assert_eq!(repo_name("git@example.com:dot-files"), "dot-files");
+        let url = root.path().join("remote.git").to_string_lossy().into_owned();
+        let report = bootstrap(&url, None, "homedir", "", &home, &config, &Options {
+            on_conflict: ConflictPolicy::Skip,
+            ..Options::default()
+        }).unwrap();
+
+        let sync_dir = root.path().join("remote").canonicalize().unwrap();
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert_eq!(config.get_sync_dir().unwrap(), sync_dir);
+        assert_eq!(
+            fs::read_link(root.path().join(".bashrc")).unwrap(),
+            sync_dir.join("homedir/.bashrc")
+        );
+        assert!(report.notes.last().unwrap().contains("1 file(s) applied"));
+        let backups_dir = home.backups_dir();
+        let backup = list_backups(&backups_dir).pop().expect("conflicting file isn't backed up");
+        assert_eq!(fs::read_to_string(backups_dir.join(backup).join(".bashrc")).unwrap(), "local");
+        let err = bootstrap(&url, None, "homedir", "", &home, &config, &Options::default());
+        assert_eq!(err.unwrap_err().exit_code(), 9);
+        let elsewhere = TempDir::new("ff").unwrap();
+        let dest = elsewhere.path().join("dot-files");
+        let err = bootstrap(&url, Some(&dest), "homedir", "", &home, &config, &Options::default());
+        assert_eq!(err.unwrap_err().exit_code(), 9);
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn bootstrap_of_whole_repo_ignores_git_dir() {
+        let root = TempDir::new("ff").unwrap();
+        let home = Home::new(root.path());
+        let config = Config::new(root.path().join(".ff/config.ini")).unwrap();
+        let origin = cloned_repo(root.path(), "origin");
+        fs::write(origin.join(".bashrc"), "synced").unwrap();
+        git(&origin, &["add", ".bashrc"]);
+        git(&origin, &["commit", "-q", "-m", "bashrc"]);
+        git(&origin, &["push", "-q"]);
+
+        let url = root.path().join("remote.git").to_string_lossy().into_owned();
+        let report = bootstrap(&url, None, ".", "", &home, &config, &Options::default()).unwrap();
+
+        assert_eq!(report.error().map(|e| e.to_string()), None);
+        assert!(fs::read_link(root.path().join(".bashrc")).is_ok());
+        assert!(!root.path().join(".git").exists());
+    }
+}
@@ -0,0 +1,356 @@
+//! keeps changes made by past actions, so the last one can be undone
+use std::ffi::OsString;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use backup::timestamp;
+use error::Error;
+use fsmove::move_path;
+use journal::*;
+use report::*;
+
+/// Changes made by single action
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// name of the entry (time when it was recorded)
+    pub name: String,
+    /// action and its arguments
+    pub args: String,
+    /// completed operations (oldest first)
+    pub done: Vec<Done>,
+}
+
+/// History stored in `<history-dir>/<timestamp>`
+///
+/// Each entry dir contains `journal` file (list of changes) and `files` dir
+/// where files replaced by the action are kept
+#[derive(Debug, Clone)]
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    /// Gives history stored in `dir`
+    pub fn new<P: AsRef<Path>>(dir: P) -> History {
+        History {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Appends entry containing `done` operations of action described by `args`
+    ///
+    /// Files moved aside by the operations are moved into the entry
+    pub fn record(&self, args: &str, done: Vec<Done>) -> Result<(), Error> {
+        if done.is_empty() {
+            return Ok(());
+        }
+        let name = timestamp();
+        let mut entry_dir = self.dir.join(&name);
+        let mut suffix = 1;
+        while entry_dir.exists() {
+            entry_dir = self.dir.join(format!("{}-{}", name, suffix));
+            suffix += 1;
+        }
+        let files_dir = entry_dir.join("files");
+        fs::create_dir_all(&files_dir)
+            .map_err(|e| Error::io(format!("Can't create dir: {:?}", files_dir), e))?;
+        let mut kept = Vec::new();
+        for (i, item) in done.into_iter().enumerate() {
+            kept.push(match item {
+                Done::Removed { path, saved } => Done::Removed {
+                    path,
+                    saved: keep(saved, &files_dir.join(i.to_string()))?,
+                },
+                Done::Moved {
+                    from,
+                    to,
+                    replaced: Some(saved),
+                } => Done::Moved {
+                    from,
+                    to,
+                    replaced: Some(keep(saved, &files_dir.join(i.to_string()))?),
+                },
+                other => other,
+            });
+        }
+        write_journal(&entry_dir.join("journal"), args, &kept)
+    }
+
+    /// Returns recorded entries, oldest first
+    pub fn entries(&self) -> Result<Vec<Entry>, Error> {
+        let mut names = match fs::read_dir(&self.dir) {
+            Err(_) => return Ok(vec![]),
+            Ok(v) => v
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect::<Vec<_>>(),
+        };
+        names.sort();
+        let mut entries = Vec::new();
+        for name in names {
+            let (args, done) = read_journal(&self.dir.join(&name).join("journal"))?;
+            entries.push(Entry { name, args, done });
+        }
+        Ok(entries)
+    }
+
+    /// Reverts operations of the last entry and removes it
+    ///
+    /// When reverting fails, operations not reverted yet are kept in the entry.
+    /// When `dry_run` is set operations are only planned.
+    pub fn undo_last(&self, dry_run: bool) -> Result<Report, Error> {
+        let entry = self.entries()?
+            .pop()
+            .ok_or_else(|| Error::NotFound("Nothing to undo".to_owned()))?;
+        let mut report = Report::new("undo");
+        if dry_run {
+            for item in entry.done.iter().rev() {
+                report.push(item.path(), Outcome::Planned, format!("undo: {}", item), None);
+            }
+            return Ok(report);
+        }
+        let entry_dir = self.dir.join(&entry.name);
+        let mut done = entry.done;
+        while let Some(item) = done.pop() {
+            if let Err(e) = item.undo() {
+                report.push(item.path(), Outcome::Failed, "", Some(e));
+                done.push(item);
+                return write_journal(&entry_dir.join("journal"), &entry.args, &done)
+                    .map(|_| report);
+            }
+            report.push(item.path(), Outcome::Done, format!("undone: {}", item), None);
+        }
+        fs::remove_dir_all(&entry_dir)
+            .map_err(|e| Error::io(format!("Can't remove {:?}", entry_dir), e))?;
+        Ok(report)
+    }
+}
+
+/// Moves file kept by `saved` to `path` (symlinks are only remembered)
+fn keep(saved: Saved, path: &Path) -> Result<Saved, Error> {
+    match saved {
+        Saved::Symlink(_) => Ok(saved),
+        Saved::File(stashed) => {
+            // history may be stored on other filesystem
+            move_path(&stashed, path)
+                .map_err(|e| Error::io(format!("Can't move file {:?} to {:?}", stashed, path), e))?;
+            Ok(Saved::File(path.to_path_buf()))
+        }
+    }
+}
+
+/// Escapes bytes which separate fields and lines of journal file
+pub(crate) fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\t' => escaped.extend_from_slice(b"\\t"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            _ => escaped.push(b),
+        }
+    }
+    escaped
+}
+
+/// Reverts `escape`
+pub(crate) fn unescape(bytes: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b != b'\\' {
+            unescaped.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(&b't') => unescaped.push(b'\t'),
+            Some(&b'n') => unescaped.push(b'\n'),
+            Some(&other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+fn saved_fields(saved: &Saved) -> (&'static str, &Path) {
+    match *saved {
+        Saved::Symlink(ref target) => ("symlink", target),
+        Saved::File(ref kept) => ("file", kept),
+    }
+}
+
+/// Writes `args` and `done` to journal file at `path`
+///
+/// Each line is a tab-separated record, e.g. `moved\t<from>\t<to>[\t<file|symlink>\t<path>]`
+fn write_journal(path: &Path, args: &str, done: &[Done]) -> Result<(), Error> {
+    let mut lines: Vec<Vec<&[u8]>> = vec![vec![b"args", args.as_bytes()]];
+    let old_modes = done
+        .iter()
+        .map(|item| match *item {
+            Done::ModeSet { old_mode, .. } => format!("{:o}", old_mode),
+            _ => String::new(),
+        })
+        .collect::<Vec<_>>();
+    for (item, old_mode) in done.iter().zip(&old_modes) {
+        let mut line: Vec<&[u8]> = Vec::new();
+        match *item {
+            Done::CreatedDirs(ref dirs) => {
+                line.push(b"created-dirs");
+                line.extend(dirs.iter().map(|d| d.as_os_str().as_bytes()));
+            }
+            Done::Symlinked(ref link) => {
+                line.push(b"symlinked");
+                line.push(link.as_os_str().as_bytes());
+            }
+            Done::Written(ref path) => {
+                line.push(b"written");
+                line.push(path.as_os_str().as_bytes());
+            }
+            Done::Removed {
+                ref path,
+                ref saved,
+            } => {
+                let (kind, saved_path) = saved_fields(saved);
+                line.push(b"removed");
+                line.push(path.as_os_str().as_bytes());
+                line.push(kind.as_bytes());
+                line.push(saved_path.as_os_str().as_bytes());
+            }
+            Done::Moved {
+                ref from,
+                ref to,
+                ref replaced,
+            } => {
+                line.push(b"moved");
+                line.push(from.as_os_str().as_bytes());
+                line.push(to.as_os_str().as_bytes());
+                if let Some(ref saved) = *replaced {
+                    let (kind, saved_path) = saved_fields(saved);
+                    line.push(kind.as_bytes());
+                    line.push(saved_path.as_os_str().as_bytes());
+                }
+            }
+            Done::ModeSet { ref path, .. } => {
+                line.push(b"mode-set");
+                line.push(path.as_os_str().as_bytes());
+                line.push(old_mode.as_bytes());
+            }
+        }
+        lines.push(line);
+    }
+    let mut content = Vec::new();
+    for line in lines {
+        let fields = line.iter().map(|f| escape(f)).collect::<Vec<_>>();
+        content.extend_from_slice(&fields.join(&b'\t'));
+        content.push(b'\n');
+    }
+    fs::File::create(path)
+        .and_then(|mut f| f.write_all(&content))
+        .map_err(|e| Error::io(format!("Can't write {:?}", path), e))
+}
+
+/// Reads args and operations written by `write_journal`
+fn read_journal(path: &Path) -> Result<(String, Vec<Done>), Error> {
+    let content = fs::read(path).map_err(|e| Error::io(format!("Can't read {:?}", path), e))?;
+    let broken = |line: &[u8]| Error::Corrupted {
+        path: path.to_path_buf(),
+        reason: format!("line: {:?}", String::from_utf8_lossy(line)),
+    };
+    let mut args = String::new();
+    let mut done = Vec::new();
+    for line in content.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+        let fields = line.split(|&b| b == b'\t')
+            .map(unescape)
+            .collect::<Vec<_>>();
+        let paths = fields[1..]
+            .iter()
+            .map(|f| PathBuf::from(OsString::from_vec(f.clone())))
+            .collect::<Vec<_>>();
+        let saved = |kind: &[u8], path: &PathBuf| match kind {
+            b"symlink" => Ok(Saved::Symlink(path.clone())),
+            b"file" => Ok(Saved::File(path.clone())),
+            _ => Err(broken(line)),
+        };
+        match (fields[0].as_slice(), paths.len()) {
+            (b"args", 1) => args = String::from_utf8_lossy(&fields[1]).into_owned(),
+            (b"created-dirs", _) => done.push(Done::CreatedDirs(paths)),
+            (b"symlinked", 1) => done.push(Done::Symlinked(paths[0].clone())),
+            (b"written", 1) => done.push(Done::Written(paths[0].clone())),
+            (b"removed", 3) => done.push(Done::Removed {
+                path: paths[0].clone(),
+                saved: saved(&fields[2], &paths[2])?,
+            }),
+            (b"moved", 2) => done.push(Done::Moved {
+                from: paths[0].clone(),
+                to: paths[1].clone(),
+                replaced: None,
+            }),
+            (b"moved", 4) => done.push(Done::Moved {
+                from: paths[0].clone(),
+                to: paths[1].clone(),
+                replaced: Some(saved(&fields[3], &paths[3])?),
+            }),
+            (b"mode-set", 2) => done.push(Done::ModeSet {
+                path: paths[0].clone(),
+                old_mode: u32::from_str_radix(&String::from_utf8_lossy(&fields[2]), 8)
+                    .map_err(|_| broken(line))?,
+            }),
+            _ => return Err(broken(line)),
+        }
+    }
+    Ok((args, done))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempdir::TempDir;
+
+    use plan::*;
+
+    #[test]
+    fn undo_last_reverts_recorded_changes() {
+        let homedir = TempDir::new("user1").unwrap();
+        let user_file = homedir.path().join(".vimrc");
+        File::create(&user_file)
+            .unwrap()
+            .write_all(b"local")
+            .unwrap();
+        let sync_file = homedir.path().join("dot-files/.vimrc");
+        let mut plan = Plan::new();
+        plan.push(Operation::CreateDir(homedir.path().join("dot-files")), "");
+        plan.push(
+            Operation::Move {
+                from: user_file.clone(),
+                to: sync_file.clone(),
+            },
+            "",
+        );
+        plan.push(
+            Operation::Symlink {
+                target: sync_file.clone(),
+                link: user_file.clone(),
+            },
+            "",
+        );
+        let history = History::new(homedir.path().join(".ff/history"));
+        let mut report = Report::new("add");
+        run_plans(&[plan], false, &mut report);
+        history.record("add --file-path\t.vimrc", report.done).unwrap();
+
+        let entries = history.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].args, "add --file-path\t.vimrc");
+        assert_eq!(entries[0].done.len(), 3);
+
+        history.undo_last(false).unwrap();
+
+        assert!(!homedir.path().join("dot-files").exists());
+        assert_eq!(fs::read(&user_file).unwrap(), b"local");
+        assert_eq!(history.entries().unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,497 @@
+//! records completed operations so they can be undone (when something fails later)
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+use error::Error;
+use fsmove::move_path;
+use plan::*;
+use report::*;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes Ctrl-C stop running operations (instead of killing the process)
+///
+/// See: `ff::journal::run_plans`
+pub fn catch_interrupts() {
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Returns `true` if Ctrl-C was pressed after `catch_interrupts` was called
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Something which was replaced by an operation and can be put back
+#[derive(Debug, Clone, PartialEq)]
+pub enum Saved {
+    /// Symlink pointing to the path
+    Symlink(PathBuf),
+    /// File moved aside to the path
+    File(PathBuf),
+}
+
+/// Completed operation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Done {
+    /// Dirs created (outermost first)
+    CreatedDirs(Vec<PathBuf>),
+    /// Symlink created at the path
+    Symlinked(PathBuf),
+    /// File written at the path
+    Written(PathBuf),
+    /// File removed from the path
+    Removed {
+        /// path of removed file
+        path: PathBuf,
+        /// what was removed
+        saved: Saved,
+    },
+    /// File moved
+    Moved {
+        /// old path of the file
+        from: PathBuf,
+        /// new path of the file
+        to: PathBuf,
+        /// what was at `to` before the move
+        replaced: Option<Saved>,
+    },
+    /// Permissions of file (or dir) set
+    ModeSet {
+        /// path of the file
+        path: PathBuf,
+        /// permission bits the file had before
+        old_mode: u32,
+    },
+}
+
+/// Moves aside `path` (symlinks are only remembered) so it can be restored later
+fn save(path: &Path) -> Result<Option<Saved>, Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Err(_) => return Ok(None),
+        Ok(v) => v,
+    };
+    // dirs are never replaced, so let the operation fail on them
+    if metadata.is_dir() {
+        return Ok(None);
+    }
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)
+            .map_err(|e| Error::io(format!("Can't read symlink {:?}", path), e))?;
+        return Ok(Some(Saved::Symlink(target)));
+    }
+    // kept in the same dir so it's on the same filesystem
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::invalid_path(path, "Can't get file name"))?;
+    let mut stashed_name = OsString::from(".");
+    stashed_name.push(file_name);
+    stashed_name.push(format!(".ff-{}", process::id()));
+    let stashed = path.with_file_name(stashed_name);
+    fs::rename(path, &stashed)
+        .map_err(|e| Error::io(format!("Can't move aside {:?} to {:?}", path, stashed), e))?;
+    Ok(Some(Saved::File(stashed)))
+}
+
+/// Removes file moved aside by `save` (it won't be restored anymore)
+fn forget(saved: &Saved) -> Result<(), Error> {
+    match *saved {
+        Saved::File(ref stashed) => fs::remove_file(stashed)
+            .map_err(|e| Error::io(format!("Can't remove {:?}", stashed), e)),
+        Saved::Symlink(_) => Ok(()),
+    }
+}
+
+/// Returns error if anything exists at `path` (so it won't be overwritten)
+fn ensure_free(path: &Path) -> Result<(), Error> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Err(Error::invalid_path(path, "Already exists")),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Puts `saved` back at `path`
+fn restore(saved: &Saved, path: &Path) -> Result<(), Error> {
+    ensure_free(path)?;
+    match *saved {
+        Saved::Symlink(ref target) => unix_fs::symlink(target, path)
+            .map_err(|e| Error::io(format!("Can't symlink {:?} to {:?}", path, target), e)),
+        Saved::File(ref stashed) => move_path(stashed, path)
+            .map_err(|e| Error::io(format!("Can't move file {:?} to {:?}", stashed, path), e)),
+    }
+}
+
+impl Done {
+    /// Returns path of the file the operation was about (for moves it's the original path)
+    pub fn path(&self) -> &Path {
+        match *self {
+            Done::CreatedDirs(ref dirs) => dirs.first().map(|d| d.as_path()).unwrap_or(Path::new("")),
+            Done::Symlinked(ref path)
+            | Done::Written(ref path)
+            | Done::Removed { ref path, .. }
+            | Done::Moved { from: ref path, .. }
+            | Done::ModeSet { ref path, .. } => path,
+        }
+    }
+
+    /// Reverts the operation
+    pub fn undo(&self) -> Result<(), Error> {
+        match *self {
+            Done::CreatedDirs(ref dirs) => {
+                for dir in dirs.iter().rev() {
+                    // dirs used by something else are left
+                    let used = fs::read_dir(dir)
+                        .map(|mut entries| entries.next().is_some())
+                        .unwrap_or(false);
+                    if used {
+                        continue;
+                    }
+                    fs::remove_dir(dir)
+                        .map_err(|e| Error::io(format!("Can't remove dir {:?}", dir), e))?;
+                }
+                Ok(())
+            }
+            Done::Symlinked(ref link) => {
+                match fs::symlink_metadata(link) {
+                    Ok(ref m) if m.file_type().is_symlink() => {}
+                    _ => return Err(Error::invalid_path(link, "Not a symlink anymore")),
+                }
+                fs::remove_file(link).map_err(|e| Error::io(format!("Can't remove {:?}", link), e))
+            }
+            Done::Written(ref path) => {
+                match fs::symlink_metadata(path) {
+                    Ok(ref m) if m.is_file() => {}
+                    _ => return Err(Error::invalid_path(path, "Not a file anymore")),
+                }
+                fs::remove_file(path).map_err(|e| Error::io(format!("Can't remove {:?}", path), e))
+            }
+            Done::Removed {
+                ref path,
+                ref saved,
+            } => restore(saved, path),
+            Done::Moved {
+                ref from,
+                ref to,
+                ref replaced,
+            } => {
+                ensure_free(from)?;
+                if let Some(parent) = from.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| Error::io(format!("Can't create dir: {:?}", parent), e))?;
+                }
+                move_path(to, from)
+                    .map_err(|e| Error::io(format!("Can't move file {:?} to {:?}", to, from), e))?;
+                match *replaced {
+                    Some(ref saved) => restore(saved, to),
+                    None => Ok(()),
+                }
+            }
+            Done::ModeSet { ref path, old_mode } => {
+                fs::set_permissions(path, fs::Permissions::from_mode(old_mode))
+                    .map_err(|e| Error::io(format!("Can't set permissions of {:?}", path), e))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Done {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Done::CreatedDirs(ref dirs) => write!(f, "created dirs: {:?}", dirs),
+            Done::Symlinked(ref link) => write!(f, "symlinked: {:?}", link),
+            Done::Written(ref path) => write!(f, "written: {:?}", path),
+            Done::Removed { ref path, .. } => write!(f, "removed: {:?}", path),
+            Done::Moved {
+                ref from, ref to, ..
+            } => write!(f, "moved: {:?} -> {:?}", from, to),
+            Done::ModeSet { ref path, .. } => write!(f, "mode set: {:?}", path),
+        }
+    }
+}
+
+/// List of completed operations which can be undone in reverse order
+#[derive(Debug, Default)]
+pub struct Journal {
+    done: Vec<Done>,
+}
+
+impl Journal {
+    /// Gives empty journal
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    /// Gives journal of `done` operations (see: `Journal::into_done`)
+    pub fn from_done(done: Vec<Done>) -> Journal {
+        Journal { done }
+    }
+
+    /// Returns completed operations (oldest first)
+    pub fn done(&self) -> &[Done] {
+        &self.done
+    }
+
+    /// Runs `operation` and records it
+    pub fn run_operation(&mut self, operation: &Operation) -> Result<(), Error> {
+        if interrupted() {
+            return Err(Error::Interrupted);
+        }
+        match *operation {
+            Operation::CreateDir(ref path) => {
+                let missing = path.ancestors()
+                    .take_while(|p| !p.exists())
+                    .map(|p| p.to_path_buf())
+                    .collect::<Vec<_>>();
+                operation.run()?;
+                if !missing.is_empty() {
+                    self.done
+                        .push(Done::CreatedDirs(missing.into_iter().rev().collect()));
+                }
+            }
+            Operation::RemoveFile(ref path) => match save(path)? {
+                // nothing to remove, so let the operation report it
+                None => operation.run()?,
+                Some(saved) => {
+                    if let Saved::Symlink(_) = saved {
+                        operation.run()?;
+                    }
+                    self.done.push(Done::Removed {
+                        path: path.clone(),
+                        saved,
+                    });
+                }
+            },
+            Operation::Symlink { ref link, .. } => {
+                operation.run()?;
+                self.done.push(Done::Symlinked(link.clone()));
+            }
+            Operation::WriteFile { ref path, .. } | Operation::CopyFile { to: ref path, .. } => {
+                let existed = fs::symlink_metadata(path).is_ok();
+                if let Err(e) = operation.run() {
+                    // partially written file is not left behind
+                    if !existed && path.is_file() {
+                        let _ = fs::remove_file(path);
+                    }
+                    return Err(e);
+                }
+                self.done.push(Done::Written(path.clone()));
+            }
+            Operation::SetMode { ref path, .. } => {
+                let old_mode = fs::metadata(path)
+                    .map(|m| m.permissions().mode() & 0o7777)
+                    .map_err(|e| Error::io(format!("Can't get permissions of {:?}", path), e))?;
+                operation.run()?;
+                self.done.push(Done::ModeSet {
+                    path: path.clone(),
+                    old_mode,
+                });
+            }
+            Operation::Move { ref from, ref to } => {
+                let replaced = save(to)?;
+                if let Err(e) = operation.run() {
+                    // replaced symlink is left untouched by failed move
+                    if let Some(ref saved @ Saved::File(_)) = replaced {
+                        restore(saved, to)?;
+                    }
+                    return Err(e);
+                }
+                self.done.push(Done::Moved {
+                    from: from.clone(),
+                    to: to.clone(),
+                    replaced,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs all steps of `plan` and records them, stops on the first failing one
+    pub fn run(&mut self, plan: &Plan) -> Result<(), Error> {
+        for step in plan.steps() {
+            self.run_operation(&step.operation)?;
+        }
+        Ok(())
+    }
+
+    /// Undoes recorded operations in reverse order
+    ///
+    /// Stops on the first failure, the rest has to be fixed manually
+    pub fn rollback(&mut self) -> Result<(), Error> {
+        while let Some(done) = self.done.pop() {
+            done.undo()?;
+        }
+        Ok(())
+    }
+
+    /// Undoes recorded operations because of `error`
+    /// and returns `Error::Reverted` (or `Error::RevertFailed`)
+    pub fn rollback_after(&mut self, error: Error) -> Error {
+        match self.rollback() {
+            Ok(()) => Error::Reverted(Box::new(error)),
+            Err(e) => Error::RevertFailed {
+                error: Box::new(error),
+                revert_error: Box::new(e),
+            },
+        }
+    }
+
+    /// Accepts recorded operations and hands them over
+    ///
+    /// Files moved aside are kept, so the caller has to take care of them
+    /// (see: `ff::history::History::record`)
+    pub fn into_done(self) -> Vec<Done> {
+        self.done
+    }
+
+    /// Accepts recorded operations, so files moved aside are removed
+    ///
+    /// Returns the first file which can't be removed (the rest is removed anyway)
+    pub fn commit(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for done in self.done.drain(..) {
+            let forgotten = match done {
+                Done::Removed { ref saved, .. }
+                | Done::Moved {
+                    replaced: Some(ref saved),
+                    ..
+                } => forget(saved),
+                _ => Ok(()),
+            };
+            if result.is_ok() {
+                result = forgotten;
+            }
+        }
+        result
+    }
+}
+
+/// Runs `plans` one after another and reports outcome of each one into `report`
+///
+/// By default the first failure (or Ctrl-C) undoes completed steps of all plans.
+/// With `keep_going` only the failed plan is undone and the rest is run anyway.
+/// Completed operations are handed over in `report.done`
+/// (see: `ff::journal::Journal::into_done`)
+pub fn run_plans(plans: &[Plan], keep_going: bool, report: &mut Report) {
+    let mut journal = Journal::new();
+    for plan in plans {
+        if let Some(reason) = plan.skipped() {
+            report.push(plan.path(), Outcome::Skipped, reason, None);
+            continue;
+        }
+        if plan.is_empty() {
+            continue;
+        }
+        if keep_going {
+            if interrupted() {
+                report.push(plan.path(), Outcome::Failed, "", Some(Error::Interrupted));
+                break;
+            }
+            let mut plan_journal = Journal::new();
+            if let Err(e) = plan_journal.run(plan) {
+                if let Err(revert_error) = plan_journal.rollback() {
+                    let error = Error::RevertFailed {
+                        error: Box::new(e),
+                        revert_error: Box::new(revert_error),
+                    };
+                    report.push(plan.path(), Outcome::Failed, "", Some(error));
+                    break;
+                }
+                report.push(plan.path(), Outcome::Failed, "", Some(e));
+                continue;
+            }
+            report.push(plan.path(), Outcome::Done, plan.done_msg(), None);
+            report.done.extend(plan_journal.into_done());
+            continue;
+        }
+        if let Err(e) = journal.run(plan) {
+            let error = journal.rollback_after(e);
+            for record in &mut report.records {
+                if record.result == Outcome::Done {
+                    record.result = Outcome::Reverted;
+                }
+            }
+            report.push(plan.path(), Outcome::Failed, "", Some(error));
+            return;
+        }
+        report.push(plan.path(), Outcome::Done, plan.done_msg(), None);
+    }
+    report.done.extend(journal.into_done());
+}
+
+/// Reports planned steps of `plans` into `report` without changing anything
+pub fn preview_plans(plans: &[Plan], report: &mut Report) {
+    for plan in plans {
+        if let Some(reason) = plan.skipped() {
+            report.push(plan.path(), Outcome::Skipped, reason, None);
+        } else if !plan.is_empty() {
+            report.push(plan.path(), Outcome::Planned, plan.preview(), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn failing_plan_reverts_completed_plans() {
+        let homedir = TempDir::new("user1").unwrap();
+        let user_file = homedir.path().join(".vimrc");
+        File::create(&user_file)
+            .unwrap()
+            .write_all(b"local")
+            .unwrap();
+        let target = homedir.path().join("dot-files/.vimrc");
+        let mut first = Plan::new();
+        first.push(Operation::CreateDir(homedir.path().join("dot-files")), "");
+        first.push(Operation::RemoveFile(user_file.clone()), "");
+        first.push(
+            Operation::Symlink {
+                target: target.clone(),
+                link: user_file.clone(),
+            },
+            "",
+        );
+        let mut second = Plan::new();
+        second.push(Operation::RemoveFile(homedir.path().join("missing")), "");
+        second.push(
+            Operation::Move {
+                from: homedir.path().join("missing"),
+                to: target,
+            },
+            "",
+        );
+
+        let mut report = Report::new("test");
+        run_plans(&[first, second], false, &mut report);
+
+        assert!(report.error().is_some());
+        assert!(!homedir.path().join("dot-files").exists());
+        let mut content = String::new();
+        File::open(&user_file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "local");
+        assert_eq!(fs::read_dir(homedir.path()).unwrap().count(), 1);
+    }
+}
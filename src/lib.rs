@@ -0,0 +1,64 @@
+//! `ff` manages dot files by moving them to sync-dir and symlinking them back to home dir
+//!
+//! See [Readme](https://github.com/xliiv/ff/blob/master/README.md) for use case.
+//! Actions return `Report` with outcome of each processed file instead of printing it:
+//!
+//! ```no_run
+//! extern crate ff;
+//!
+//! use ff::{Home, Options, SyncDir};
+//!
+//! # fn main() -> Result<(), ff::Error> {
+//! let home = Home::current()?;
+//! let sync_dir = SyncDir::new(home.path().join("dot-files"));
+//! let options = Options {
+//!     dry_run: true,
+//!     ..Options::default()
+//! };
+//! let report = ff::apply(&home, &sync_dir, &options)?;
+//! for record in &report.records {
+//!     println!("{:?}: {}", record.path, record.result);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#![deny(missing_docs)]
+extern crate age;
+extern crate cursive;
+extern crate ignore;
+extern crate ini;
+extern crate libc;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
+#[cfg(test)]
+extern crate tempdir;
+extern crate walkdir;
+
+pub mod backup;
+pub mod config;
+pub mod conflict;
+pub mod copies;
+pub mod core;
+pub mod error;
+pub mod ffignore;
+pub mod fsmove;
+pub mod git;
+pub mod history;
+pub mod journal;
+pub mod modes;
+pub mod paths;
+pub mod plan;
+pub mod report;
+pub mod secret;
+pub mod status;
+pub mod template;
+
+pub use core::{add, apply, apply_layers, init, pull, remove, Options};
+pub use error::Error;
+pub use paths::{Home, SyncDir};
+pub use report::{Format, Outcome, Record, Report};
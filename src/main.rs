@@ -1,19 +1,19 @@
 //! See [Readme](https://github.com/xliiv/ff/blob/master/README.md) for use case
+//!
+//! The binary is a thin front-end (CLI and TUI) over `ff` library
 
 #![deny(missing_docs)]
 #[macro_use]
 extern crate clap;
+extern crate ff;
 extern crate fui;
-extern crate ini;
-extern crate tempdir;
-extern crate walkdir;
 
-pub mod cli;
-pub mod config;
-pub mod core;
+mod cli;
+
+use std::process;
 
 use cli::*;
 
 fn main() {
-    run_cli();
+    process::exit(run_cli());
 }
@@ -0,0 +1,128 @@
+//! permissions of tracked files recorded in sync-dir manifest (VCS keeps only executable bit)
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use error::Error;
+use history::{escape, unescape};
+use paths::SyncDir;
+
+/// Name of manifest file stored in sync-dir (or sync-subdir) root
+pub const MODES_FILE: &str = ".ffmodes";
+
+/// Returns permission bits of `path` (symlinks are followed), `None` if it doesn't exist
+pub fn mode_of(path: &Path) -> Option<u32> {
+    fs::metadata(path)
+        .ok()
+        .map(|m| m.permissions().mode() & 0o7777)
+}
+
+/// Permissions of files and dirs, each stored under its path relative to sync-dir
+/// (which is also its path relative to home dir)
+///
+/// `ff add` records them and `ff apply` restores them (see: `ff::core::plan_modes`)
+#[derive(Debug)]
+pub struct Modes {
+    path: PathBuf,
+    modes: BTreeMap<PathBuf, u32>,
+}
+
+impl Modes {
+    /// Loads manifest of `sync_dir` (nothing is recorded if it doesn't exist)
+    ///
+    /// Each line is: `<octal mode>\t<relative path>`
+    pub fn load(sync_dir: &SyncDir) -> Result<Modes, Error> {
+        let path = sync_dir.path().join(MODES_FILE);
+        let mut modes = BTreeMap::new();
+        if path.exists() {
+            let content =
+                fs::read(&path).map_err(|e| Error::io(format!("Can't read {:?}", path), e))?;
+            for line in content.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                let fields = line.splitn(2, |&b| b == b'\t').collect::<Vec<_>>();
+                let mode = String::from_utf8_lossy(fields[0]);
+                match (u32::from_str_radix(&mode, 8), fields.get(1)) {
+                    (Ok(mode), Some(rel_path)) => {
+                        modes.insert(PathBuf::from(OsString::from_vec(unescape(rel_path))), mode);
+                    }
+                    _ => {
+                        return Err(Error::Corrupted {
+                            path,
+                            reason: format!("line: {:?}", String::from_utf8_lossy(line)),
+                        })
+                    }
+                }
+            }
+        }
+        Ok(Modes { path, modes })
+    }
+
+    /// Returns mode recorded for `rel_path`
+    pub fn get(&self, rel_path: &Path) -> Option<u32> {
+        self.modes.get(rel_path).cloned()
+    }
+
+    /// Records `mode` of `rel_path`, returns `true` if it wasn't recorded already
+    pub fn set(&mut self, rel_path: &Path, mode: u32) -> bool {
+        if self.get(rel_path) == Some(mode) {
+            return false;
+        }
+        self.modes.insert(rel_path.to_path_buf(), mode);
+        true
+    }
+
+    /// Saves recorded modes (see: `load`)
+    pub fn save(&self) -> Result<(), Error> {
+        let mut content = Vec::new();
+        for (rel_path, mode) in &self.modes {
+            content.extend_from_slice(format!("{:04o}\t", mode).as_bytes());
+            content.extend(escape(rel_path.as_os_str().as_bytes()));
+            content.push(b'\n');
+        }
+        fs::write(&self.path, content).map_err(|e| Error::io(format!("Can't write {:?}", self.path), e))
+    }
+
+    /// Returns path of the manifest
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Home-dir file (or dir) whose permissions differ from recorded ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeDrift {
+    /// file which has wrong permissions (sync-dir file when home-dir one is its symlink)
+    pub path: PathBuf,
+    /// recorded permissions
+    pub recorded: u32,
+    /// current permissions
+    pub actual: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn modes_are_saved_and_loaded() {
+        let dir = TempDir::new("ff").unwrap();
+        let sync_dir = SyncDir::new(dir.path());
+        fs::write(dir.path().join("script"), "").unwrap();
+        fs::set_permissions(dir.path().join("script"), fs::Permissions::from_mode(0o750)).unwrap();
+
+        let mut modes = Modes::load(&sync_dir).unwrap();
+        assert!(modes.set(Path::new(".ssh"), 0o700));
+        assert!(modes.set(Path::new("tab\there"), mode_of(&dir.path().join("script")).unwrap()));
+        assert!(!modes.set(Path::new(".ssh"), 0o700));
+        modes.save().unwrap();
+
+        let modes = Modes::load(&sync_dir).unwrap();
+        assert_eq!(modes.get(Path::new(".ssh")), Some(0o700));
+        assert_eq!(modes.get(Path::new("tab\there")), Some(0o750));
+        assert_eq!(modes.get(Path::new(".bashrc")), None);
+        assert_eq!(fs::read_to_string(dir.path().join(MODES_FILE)).unwrap().lines().next(), Some("0700\t.ssh"));
+    }
+}
@@ -0,0 +1,170 @@
+//! home dir and sync dir which dot files are moved between
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use config::Config;
+use error::Error;
+
+/// User's home dir, the place where dot files are expected by programs
+///
+/// `ff` keeps its own data (config, backups, history) in `<home>/.ff`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Home {
+    path: PathBuf,
+}
+
+impl Home {
+    /// Gives home dir at `path`
+    pub fn new<P: AsRef<Path>>(path: P) -> Home {
+        Home {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Gives home dir of current user
+    pub fn current() -> Result<Home, Error> {
+        env::home_dir().map(Home::new).ok_or(Error::NoHomeDir)
+    }
+
+    /// Returns path to home dir
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns `true` if `path` is inside home dir
+    pub fn contains<P: AsRef<Path>>(&self, path: P) -> bool {
+        path.as_ref().starts_with(&self.path)
+    }
+
+    /// Returns path to config file
+    pub fn config_file(&self) -> PathBuf {
+        self.path.join(".ff/config.ini")
+    }
+
+    /// Returns path to dir where backups of replaced home-dir files are stored
+    pub fn backups_dir(&self) -> PathBuf {
+        self.path.join(".ff/backups")
+    }
+
+    /// Returns path to file where hashes of home-dir copies are stored
+    /// (see: `ff::copies::Copies`)
+    pub fn copies_file(&self) -> PathBuf {
+        self.path.join(".ff/copies")
+    }
+
+    /// Returns path to dir where changes made by past actions are stored
+    pub fn history_dir(&self) -> PathBuf {
+        self.path.join(".ff/history")
+    }
+}
+
+/// Dir where dot files are stored, its contents mirror home dir
+///
+/// For example `<sync-dir>/.config/nvim/init.vim` is linked from `<home>/.config/nvim/init.vim`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncDir {
+    path: PathBuf,
+}
+
+impl SyncDir {
+    /// Gives sync-dir at `path`
+    pub fn new<P: AsRef<Path>>(path: P) -> SyncDir {
+        SyncDir {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Gives sync-dir saved in `config` (see: `ff::core::init`)
+    pub fn from_config(config: &Config) -> Result<SyncDir, Error> {
+        Ok(SyncDir::new(config.get_sync_dir()?))
+    }
+
+    /// Gives sync-subdir `name` which is used as sync-dir (the same dir if `name` is empty)
+    pub fn subdir<P: AsRef<Path>>(&self, name: P) -> SyncDir {
+        if name.as_ref().as_os_str().is_empty() {
+            return self.clone();
+        }
+        SyncDir::new(self.path.join(name))
+    }
+
+    /// Gives layers (sync-subdirs) of `profile` defined in `config`
+    /// (see: `ff::core::apply_layers`)
+    pub fn layers(&self, config: &Config, profile: &str) -> Result<Vec<SyncDir>, Error> {
+        Ok(config
+            .get_layers(profile)?
+            .iter()
+            .map(|layer| self.subdir(layer))
+            .collect())
+    }
+
+    /// Returns path to sync-dir
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Returns path leading from `dir` to `path` (both absolute), e.g. `../dot-files/.bashrc`
+///
+/// Paths are compared as they are written, symlinks on the way are not resolved
+pub fn relative_path(dir: &Path, path: &Path) -> PathBuf {
+    let dir = dir.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect::<Vec<_>>();
+    let path = path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect::<Vec<_>>();
+    let common = dir.iter()
+        .zip(path.iter())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    for component in &path[common..] {
+        relative.push(component.as_os_str());
+    }
+    relative
+}
+
+/// Returns target of symlink `link`, relative target is joined to dir of `link`
+pub fn resolve_link(link: &Path) -> io::Result<PathBuf> {
+    let target = fs::read_link(link)?;
+    if target.is_absolute() {
+        return Ok(target);
+    }
+    Ok(link.parent().unwrap_or_else(|| Path::new("/")).join(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_subdir_is_the_same_dir() {
+        let sync_dir = SyncDir::new("/home/joe/dot-files");
+
+        assert_eq!(sync_dir.subdir("").path(), Path::new("/home/joe/dot-files"));
+        assert_eq!(
+            sync_dir.subdir("laptop").path(),
+            Path::new("/home/joe/dot-files/laptop")
+        );
+    }
+
+    #[test]
+    fn relative_path_climbs_up_to_common_dir() {
+        assert_eq!(
+            relative_path(
+                Path::new("/home/joe/.config/nvim"),
+                Path::new("/home/joe/dot-files/./.config/nvim/init.vim")
+            ),
+            Path::new("../../dot-files/.config/nvim/init.vim")
+        );
+        assert_eq!(
+            relative_path(Path::new("/home/joe"), Path::new("/home/joe/dot-files/.bashrc")),
+            Path::new("dot-files/.bashrc")
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! describes filesystem changes planned by `ff` (so they can be previewed or run)
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs as unix_fs;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use error::Error;
+use fsmove::move_path;
+use journal::*;
+
+/// Single filesystem change
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Creates dir (including missing parents)
+    CreateDir(PathBuf),
+    /// Removes file
+    RemoveFile(PathBuf),
+    /// Creates symlink
+    Symlink {
+        /// path the symlink points to
+        target: PathBuf,
+        /// path of the symlink itself
+        link: PathBuf,
+    },
+    /// Moves file (replacing destination if exists)
+    Move {
+        /// current path of the file
+        from: PathBuf,
+        /// new path of the file
+        to: PathBuf,
+    },
+    /// Creates file with given contents (nothing can exist at the path)
+    WriteFile {
+        /// path of the file
+        path: PathBuf,
+        /// what is written to the file
+        contents: Vec<u8>,
+        /// permissions of the file (default ones if not given)
+        mode: Option<u32>,
+    },
+    /// Sets permissions of file (or dir)
+    SetMode {
+        /// path of the file
+        path: PathBuf,
+        /// permission bits, e.g. `0o700`
+        mode: u32,
+    },
+    /// Copies file with its permissions (nothing can exist at the destination)
+    CopyFile {
+        /// path of the copied file
+        from: PathBuf,
+        /// path of the copy
+        to: PathBuf,
+    },
+}
+
+impl Operation {
+    /// Performs the change on filesystem
+    pub fn run(&self) -> Result<(), Error> {
+        match *self {
+            Operation::CreateDir(ref path) => fs::create_dir_all(path)
+                .map_err(|e| Error::io(format!("Can't create dir: {:?}", path), e)),
+            Operation::RemoveFile(ref path) => {
+                fs::remove_file(path).map_err(|e| Error::io(format!("Can't remove {:?}", path), e))
+            }
+            Operation::Symlink {
+                ref target,
+                ref link,
+            } => unix_fs::symlink(target, link)
+                .map_err(|e| Error::io(format!("Can't symlink {:?} to {:?}", link, target), e)),
+            Operation::Move { ref from, ref to } => move_path(from, to)
+                .map_err(|e| Error::io(format!("Can't move file {:?} to {:?}", from, to), e)),
+            Operation::WriteFile {
+                ref path,
+                ref contents,
+                mode,
+            } => OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(mode.unwrap_or(0o666))
+                .open(path)
+                .and_then(|mut file| file.write_all(contents))
+                .map_err(|e| Error::io(format!("Can't write file {:?}", path), e)),
+            Operation::SetMode { ref path, mode } => {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                    .map_err(|e| Error::io(format!("Can't set permissions of {:?}", path), e))
+            }
+            Operation::CopyFile { ref from, ref to } => {
+                if fs::symlink_metadata(to).is_ok() {
+                    return Err(Error::io(
+                        format!("Can't copy file {:?} to {:?}", from, to),
+                        io::Error::from(io::ErrorKind::AlreadyExists),
+                    ));
+                }
+                fs::copy(from, to)
+                    .map(|_| ())
+                    .map_err(|e| Error::io(format!("Can't copy file {:?} to {:?}", from, to), e))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operation::CreateDir(ref path) => write!(f, "create dir: {:?}", path),
+            Operation::RemoveFile(ref path) => write!(f, "remove file: {:?}", path),
+            Operation::Symlink {
+                ref target,
+                ref link,
+            } => write!(f, "symlink: {:?} -> {:?}", link, target),
+            Operation::Move { ref from, ref to } => write!(f, "move: {:?} -> {:?}", from, to),
+            Operation::WriteFile {
+                ref path,
+                ref contents,
+                mode: None,
+            } => write!(f, "write file: {:?} ({} bytes)", path, contents.len()),
+            Operation::WriteFile {
+                ref path,
+                ref contents,
+                mode: Some(mode),
+            } => write!(f, "write file: {:?} ({} bytes, mode: {:o})", path, contents.len(), mode),
+            Operation::SetMode { ref path, mode } => write!(f, "set mode: {:?} ({:04o})", path, mode),
+            Operation::CopyFile { ref from, ref to } => write!(f, "copy file: {:?} -> {:?}", from, to),
+        }
+    }
+}
+
+/// `Operation` together with the reason why it's needed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// change to perform
+    pub operation: Operation,
+    /// why the change is needed
+    pub reason: String,
+}
+
+/// Ordered list of `Step`s previewed or run as a whole
+#[derive(Debug, Default)]
+pub struct Plan {
+    path: PathBuf,
+    steps: Vec<Step>,
+    done_msg: Option<String>,
+    skipped: Option<String>,
+}
+
+impl Plan {
+    /// Gives empty plan
+    pub fn new() -> Plan {
+        Plan::default()
+    }
+
+    /// Gives empty plan of changes to `path` (file reported by `ff::journal::run_plans`)
+    pub fn for_file<P: AsRef<Path>>(path: P) -> Plan {
+        Plan {
+            path: path.as_ref().to_path_buf(),
+            ..Plan::default()
+        }
+    }
+
+    /// Returns path of the file which is changed
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Marks the file as left untouched on purpose (`reason` explains why)
+    pub fn skip<T: Into<String>>(&mut self, reason: T) {
+        self.steps.clear();
+        self.skipped = Some(reason.into());
+    }
+
+    /// Returns reason set by `skip` (if any)
+    pub fn skipped(&self) -> Option<&str> {
+        self.skipped.as_deref()
+    }
+
+    /// Appends `operation` justified by `reason`
+    pub fn push<T: Into<String>>(&mut self, operation: Operation, reason: T) {
+        self.steps.push(Step {
+            operation,
+            reason: reason.into(),
+        });
+    }
+
+    /// Sets message printed when the plan is run successfully
+    pub fn on_done<T: Into<String>>(&mut self, msg: T) {
+        self.done_msg = Some(msg.into());
+    }
+
+    /// Returns planned steps
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Returns `true` if there is nothing to do
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Returns planned steps (one per line) without changing anything
+    pub fn preview(&self) -> String {
+        self.steps
+            .iter()
+            .map(|step| format!("{} ({})", step.operation, step.reason))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Returns message set by `on_done`
+    pub fn done_msg(&self) -> &str {
+        self.done_msg.as_deref().unwrap_or("")
+    }
+
+    /// Runs steps in order, the first failing one undoes those already completed
+    ///
+    /// Error is returned also when the steps are completed but files they replaced
+    /// can't be removed
+    pub fn run(&self) -> Result<(), Error> {
+        let mut journal = Journal::new();
+        if let Err(e) = journal.run(self) {
+            if journal.done().is_empty() {
+                return Err(e);
+            }
+            return Err(journal.rollback_after(e));
+        }
+        journal.commit()
+    }
+}
@@ -0,0 +1,229 @@
+//! outcome of actions for each processed file (printed as text or JSON)
+use std::fmt;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde_json;
+
+use error::Error;
+use journal::*;
+
+/// Output format of actions
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    /// Messages readable by humans
+    #[default]
+    Text,
+    /// One JSON object per line (per processed file)
+    Json,
+}
+
+/// Format names accepted by `Format::from_str`
+pub const FORMATS: [&str; 2] = ["text", "json"];
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format, Error> {
+        match s {
+            "" | "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::InvalidArgument(format!(
+                "Unknown format: {:?} (expected one of: {})",
+                s,
+                FORMATS.join(", ")
+            ))),
+        }
+    }
+}
+
+/// What happened to processed file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// File was changed
+    Done,
+    /// File would be changed (dry run)
+    Planned,
+    /// File was left untouched on purpose, e.g. conflict
+    Skipped,
+    /// File was changed, but the change was reverted because of other failure
+    Reverted,
+    /// File couldn't be changed
+    Failed,
+}
+
+impl Outcome {
+    /// Returns short name of the outcome (used by JSON format)
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Outcome::Done => "done",
+            Outcome::Planned => "planned",
+            Outcome::Skipped => "skipped",
+            Outcome::Reverted => "reverted",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Outcome of single file
+#[derive(Debug)]
+pub struct Record {
+    /// processed file
+    pub path: PathBuf,
+    /// action which processed the file
+    pub action: String,
+    /// what happened to the file
+    pub result: Outcome,
+    /// message readable by humans (e.g. planned steps or why file was skipped)
+    pub message: String,
+    /// why the file failed
+    pub error: Option<Error>,
+}
+
+impl Record {
+    /// Returns the record as JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "path": self.path.to_string_lossy(),
+            "action": self.action,
+            "result": self.result.name(),
+            "error": self.error.as_ref().map(|e| e.to_string()),
+        })
+    }
+}
+
+/// Outcome of action
+#[derive(Debug, Default)]
+pub struct Report {
+    /// action which is reported
+    pub action: String,
+    /// outcome of each processed file
+    pub records: Vec<Record>,
+    /// completed operations (see: `ff::journal::Journal::into_done`)
+    pub done: Vec<Done>,
+    /// remarks about the whole action, e.g. where replaced files are backed up
+    pub notes: Vec<String>,
+}
+
+impl Report {
+    /// Gives empty report of `action`
+    pub fn new<T: Into<String>>(action: T) -> Report {
+        Report {
+            action: action.into(),
+            ..Report::default()
+        }
+    }
+
+    /// Appends outcome of `path`
+    pub fn push<P, T>(&mut self, path: P, result: Outcome, message: T, error: Option<Error>)
+    where
+        P: AsRef<Path>,
+        T: Into<String>,
+    {
+        self.records.push(Record {
+            path: path.as_ref().to_path_buf(),
+            action: self.action.clone(),
+            result,
+            message: message.into(),
+            error,
+        });
+    }
+
+    /// Appends records, completed operations and notes of `other` report
+    pub fn extend(&mut self, other: Report) {
+        self.records.extend(other.records);
+        self.done.extend(other.done);
+        self.notes.extend(other.notes);
+    }
+
+    /// Accepts completed operations (`done`), so files they moved aside are removed
+    ///
+    /// Library callers which don't hand `done` over to `ff::history::History::record`
+    /// have to call it, otherwise replaced files are left as `.<name>.ff-<pid>`
+    pub fn commit(&mut self) -> Result<(), Error> {
+        Journal::from_done(mem::take(&mut self.done)).commit()
+    }
+
+    /// Returns number of records with `result`
+    pub fn count(&self, result: Outcome) -> usize {
+        self.records.iter().filter(|r| r.result == result).count()
+    }
+
+    /// Returns error of the first failed file (if any)
+    pub fn error(&self) -> Option<&Error> {
+        self.records.iter().filter_map(|r| r.error.as_ref()).next()
+    }
+
+    /// Prints records in `format` (notes are printed only as text)
+    pub fn print(&self, format: Format) {
+        for record in &self.records {
+            match format {
+                Format::Json => println!("{}", record.to_json()),
+                Format::Text => match record.result {
+                    Outcome::Done | Outcome::Planned => println!("{}", record.message),
+                    Outcome::Skipped => println!("SKIPPING: {}", record.message),
+                    Outcome::Reverted => println!("reverted: {:?}", record.path),
+                    Outcome::Failed => match record.error {
+                        Some(ref e) => println!("FAILED: {}", e),
+                        None => println!("FAILED: {:?}", record.path),
+                    },
+                },
+            }
+        }
+        if format == Format::Text {
+            for note in &self.notes {
+                println!("{}", note);
+            }
+        }
+    }
+}
+
+/// Prints `error` of `action` which failed as a whole in `format`
+pub fn print_error(action: &str, error: &Error, format: Format) {
+    match format {
+        Format::Text => println!("{}", error),
+        Format::Json => println!(
+            "{}",
+            json!({
+                "path": null,
+                "action": action,
+                "result": Outcome::Failed.name(),
+                "error": error.to_string(),
+            })
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn error_returns_first_failure() {
+        let mut report = Report::new("add");
+        report.push("/home/joe/.vimrc", Outcome::Done, "added", None);
+        report.push(
+            "/home/joe/.bashrc",
+            Outcome::Failed,
+            "",
+            Some(Error::io(
+                "Can't move",
+                io::Error::from(io::ErrorKind::PermissionDenied),
+            )),
+        );
+
+        assert_eq!(report.error().map(|e| e.exit_code()), Some(4));
+        assert_eq!(
+            report.records[1].to_json().to_string(),
+            r#"{"action":"add","error":"Can't move (permission denied)","path":"/home/joe/.bashrc","result":"failed"}"#
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! encrypted `.age` files of sync-dir (secrets), decrypted into private home-dir files
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use age;
+use age::secrecy::SecretString;
+use cursive::Cursive;
+use cursive::traits::Identifiable;
+use cursive::views::{Dialog, EditView};
+
+use copies::*;
+use error::Error;
+use status::FileState;
+
+/// Suffix of sync-dir files which are decrypted into home-dir (without the suffix)
+pub const ENCRYPTED_SUFFIX: &str = ".age";
+
+/// Permissions of decrypted home-dir files (readable by the owner only)
+pub const SECRET_MODE: u32 = 0o600;
+
+/// Environment variable the passphrase is taken from (instead of asking for it)
+pub const PASSPHRASE_VAR: &str = "FF_PASSPHRASE";
+
+/// scrypt work factor used for encryption (tests use the cheapest one)
+#[cfg(not(test))]
+const WORK_FACTOR: Option<u8> = None;
+#[cfg(test)]
+const WORK_FACTOR: Option<u8> = Some(1);
+
+/// Passphrase secrets are encrypted with (it's never printed)
+#[derive(Clone, PartialEq)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    /// Gives passphrase `text`
+    pub fn new<T: Into<String>>(text: T) -> Passphrase {
+        Passphrase(text.into())
+    }
+
+    /// Gives passphrase set in `PASSPHRASE_VAR` environment variable (if any)
+    pub fn from_env() -> Option<Passphrase> {
+        env::var(PASSPHRASE_VAR).ok().map(Passphrase::new)
+    }
+
+    /// Asks for passphrase in TUI, returns `None` when user cancels
+    pub fn ask() -> Option<Passphrase> {
+        let text = Rc::new(RefCell::new(None));
+        let mut c = Cursive::new();
+        let submitted = Rc::clone(&text);
+        let edit = EditView::new().secret().on_submit(move |c, value| {
+            *submitted.borrow_mut() = Some(value.to_owned());
+            c.quit();
+        });
+        let submitted = Rc::clone(&text);
+        c.add_layer(
+            Dialog::around(edit.with_id("passphrase"))
+                .title("Passphrase of secrets")
+                .button("Ok", move |c| {
+                    let value = c.call_on_id("passphrase", |v: &mut EditView| v.get_content());
+                    *submitted.borrow_mut() = value.map(|v| (*v).clone());
+                    c.quit();
+                })
+                .button("Cancel", |c| c.quit()),
+        );
+        c.run();
+        let text = text.borrow_mut().take();
+        text.map(Passphrase::new)
+    }
+
+    fn secret(&self) -> SecretString {
+        SecretString::from(self.0.clone())
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Passphrase(***)")
+    }
+}
+
+/// Returns `true` if `path` is a secret (its name ends with `ENCRYPTED_SUFFIX`)
+pub fn is_encrypted(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.as_bytes().ends_with(ENCRYPTED_SUFFIX.as_bytes()))
+        .unwrap_or(false)
+}
+
+/// Returns `path` of secret without `ENCRYPTED_SUFFIX` (other paths are returned as they are)
+pub fn decrypted_path(path: &Path) -> PathBuf {
+    match path.file_name().map(|name| name.as_bytes()) {
+        Some(name) if is_encrypted(path) && name.len() > ENCRYPTED_SUFFIX.len() => {
+            path.with_file_name(OsStr::from_bytes(&name[..name.len() - ENCRYPTED_SUFFIX.len()]))
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Returns `path` with `ENCRYPTED_SUFFIX` appended, e.g. `.netrc.age` for `.netrc`
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(ENCRYPTED_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Encrypts `plaintext` of `path` with `passphrase` (age format, ASCII armored)
+pub fn encrypt(path: &Path, plaintext: &[u8], passphrase: &Passphrase) -> Result<Vec<u8>, Error> {
+    let mut recipient = age::scrypt::Recipient::new(passphrase.secret());
+    if let Some(log_n) = WORK_FACTOR {
+        recipient.set_work_factor(log_n);
+    }
+    age::encrypt_and_armor(&recipient, plaintext)
+        .map(String::into_bytes)
+        .map_err(|e| Error::Secret {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+}
+
+/// Decrypts `ciphertext` of `path` with `passphrase` (see: `encrypt`)
+pub fn decrypt(path: &Path, ciphertext: &[u8], passphrase: &Passphrase) -> Result<Vec<u8>, Error> {
+    let identity = age::scrypt::Identity::new(passphrase.secret());
+    age::decrypt(&identity, ciphertext).map_err(|e| Error::Secret {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })
+}
+
+/// Returns `passphrase` needed for secret `path` or error when it's not given
+fn given<'a>(path: &Path, passphrase: Option<&'a Passphrase>) -> Result<&'a Passphrase, Error> {
+    passphrase.ok_or_else(|| Error::Secret {
+        path: path.to_path_buf(),
+        reason: format!("no passphrase given, set {}", PASSPHRASE_VAR),
+    })
+}
+
+/// Encrypts contents of `file` with `passphrase` (fails when it's not given)
+pub fn encrypt_file(file: &Path, passphrase: Option<&Passphrase>) -> Result<Vec<u8>, Error> {
+    let passphrase = given(file, passphrase)?;
+    let plaintext = fs::read(file).map_err(|e| Error::io(format!("Can't read {:?}", file), e))?;
+    encrypt(file, &plaintext, passphrase)
+}
+
+/// Decrypts secret `sync_file` with `passphrase` (fails when it's not given)
+pub fn decrypt_file(sync_file: &Path, passphrase: Option<&Passphrase>) -> Result<Vec<u8>, Error> {
+    let passphrase = given(sync_file, passphrase)?;
+    let ciphertext =
+        fs::read(sync_file).map_err(|e| Error::io(format!("Can't read {:?}", sync_file), e))?;
+    decrypt(sync_file, &ciphertext, passphrase)
+}
+
+/// Returns hash recorded for decrypted home-dir file (see: `ff::copies::Copies`)
+///
+/// It's made of hash of secret (`ciphertext`) and hash of its `plaintext` salted with `salt`
+/// (so weak secrets can't be guessed from it), as they always differ
+pub fn secret_hash(ciphertext: &[u8], plaintext: &[u8], salt: &str) -> String {
+    format!("{}:{}:{}", hash(ciphertext), salt, salted_hash(salt, plaintext))
+}
+
+/// Returns hash of `plaintext` prefixed with `salt`
+fn salted_hash(salt: &str, plaintext: &[u8]) -> String {
+    hash(&[salt.as_bytes(), plaintext].concat())
+}
+
+/// Returns random salt (as hex) for `secret_hash`
+fn new_salt() -> Result<String, Error> {
+    let mut salt = [0u8; 16];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut salt))
+        .map_err(|e| Error::io("Can't read /dev/urandom", e))?;
+    Ok(salt.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Returns `true` if `recorded` `secret_hash` was made of `plaintext`
+pub fn is_recorded_plaintext(recorded: &str, plaintext: &[u8]) -> bool {
+    match recorded.splitn(3, ':').collect::<Vec<_>>()[..] {
+        [_, salt, plain_hash] => salted_hash(salt, plaintext) == plain_hash,
+        _ => false,
+    }
+}
+
+/// Records `secret_hash` of each secret (sync-dir file) and its decrypted home-dir file
+/// in `copies`, returns `true` if any record changed
+pub fn record_secrets(copies: &mut Copies, secrets: &[(PathBuf, PathBuf)]) -> Result<bool, Error> {
+    let mut changed = false;
+    for (sync_file, user_file) in secrets {
+        let read = |path: &Path| fs::read(path).map_err(|e| Error::io(format!("Can't read {:?}", path), e));
+        let (ciphertext, plaintext) = (read(sync_file)?, read(user_file)?);
+        let unchanged = copies.get(user_file).is_some_and(|recorded| {
+            recorded.starts_with(&format!("{}:", hash(&ciphertext)))
+                && is_recorded_plaintext(recorded, &plaintext)
+        });
+        if !unchanged {
+            changed |= copies.set(user_file, secret_hash(&ciphertext, &plaintext, &new_salt()?));
+        }
+    }
+    Ok(changed)
+}
+
+/// Returns state of `user_file` which should be decrypted secret `sync_file`
+///
+/// `recorded` is `secret_hash` of both when `user_file` was decrypted (or encrypted),
+/// so no passphrase is needed. Symlinks and files without record are `Shadowed`
+pub fn secret_state(sync_file: &Path, user_file: &Path, recorded: Option<&str>) -> FileState {
+    match fs::symlink_metadata(user_file) {
+        Err(_) => return FileState::Missing,
+        Ok(ref m) if !m.is_file() => return FileState::Shadowed,
+        Ok(_) => {}
+    }
+    let (sync_hash, plaintext) = match (file_hash(sync_file), fs::read(user_file)) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return FileState::Shadowed,
+    };
+    let recorded = match recorded {
+        Some(v) if v.split(':').count() == 3 => v,
+        _ => return FileState::Shadowed,
+    };
+    let same_secret = recorded.starts_with(&format!("{}:", sync_hash));
+    match (same_secret, is_recorded_plaintext(recorded, &plaintext)) {
+        (true, true) => FileState::Decrypted,
+        (true, false) => FileState::ModifiedInHome,
+        (false, true) => FileState::ModifiedInSyncDir,
+        (false, false) => FileState::ModifiedInBoth,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn secrets_are_decrypted_with_the_same_passphrase_and_drift_is_detected() {
+        let dir = TempDir::new("ff").unwrap();
+        let sync_file = dir.path().join(".netrc.age");
+        let user_file = dir.path().join(".netrc");
+        let passphrase = Passphrase::new("correct horse");
+
+        let ciphertext = encrypt(&user_file, b"password", &passphrase).unwrap();
+        fs::write(&sync_file, &ciphertext).unwrap();
+        fs::write(&user_file, b"password").unwrap();
+
+        assert!(!ciphertext.windows(8).any(|w| w == b"password"));
+        assert_eq!(decrypt_file(&sync_file, Some(&passphrase)).unwrap(), b"password");
+        assert_eq!(decrypt_file(&sync_file, Some(&Passphrase::new("wrong"))).unwrap_err().exit_code(), 14);
+        assert!(decrypt_file(&sync_file, None).is_err());
+        assert_eq!(decrypted_path(&sync_file), user_file);
+        assert_eq!(encrypted_path(&user_file), sync_file);
+        assert_eq!(format!("{:?}", passphrase), "Passphrase(***)");
+
+        let recorded = secret_hash(&ciphertext, b"password", "5a17");
+        assert!(!recorded.contains(&hash(b"password")));
+        assert!(is_recorded_plaintext(&recorded, b"password"));
+        assert_eq!(secret_state(&sync_file, &user_file, Some(&recorded)), FileState::Decrypted);
+        assert_eq!(secret_state(&sync_file, &user_file, None), FileState::Shadowed);
+        fs::write(&user_file, b"changed").unwrap();
+        assert_eq!(secret_state(&sync_file, &user_file, Some(&recorded)), FileState::ModifiedInHome);
+        fs::write(&sync_file, encrypt(&user_file, b"other", &passphrase).unwrap()).unwrap();
+        assert_eq!(secret_state(&sync_file, &user_file, Some(&recorded)), FileState::ModifiedInBoth);
+    }
+}
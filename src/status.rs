@@ -0,0 +1,406 @@
+//! reports state of home-dir counterparts of files stored in sync-dir
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use copies::*;
+use core::*;
+use error::Error;
+use modes::*;
+use paths::*;
+use report::Format;
+use secret::*;
+use template::*;
+
+/// State of home-dir counterpart of sync-dir file
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileState {
+    /// Symlink pointing to sync-dir file
+    Linked,
+    /// Nothing exists at home-dir path
+    Missing,
+    /// Regular file (or dir) which shadows sync-dir file
+    Shadowed,
+    /// Symlink pointing to other existing file
+    Elsewhere(PathBuf),
+    /// Symlink pointing to non-existing file
+    Dangling(PathBuf),
+    /// File with the contents of rendered template
+    Rendered,
+    /// File (or symlink) which differs from rendered template (`ff apply` updates it)
+    Stale,
+    /// Copy with the same contents as sync-dir file
+    Copied,
+    /// Copy modified in home-dir since copied (`ff pull` brings it to sync-dir)
+    ModifiedInHome,
+    /// Sync-dir file modified since copied (`ff apply` updates the copy)
+    ModifiedInSyncDir,
+    /// Both copy and sync-dir file modified since copied
+    ModifiedInBoth,
+    /// File with the contents of decrypted secret (see: `ff::secret`)
+    Decrypted,
+}
+
+impl FileState {
+    /// Returns short name of the state (used by porcelain format)
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FileState::Linked => "linked",
+            FileState::Missing => "missing",
+            FileState::Shadowed => "shadowed",
+            FileState::Elsewhere(_) => "elsewhere",
+            FileState::Dangling(_) => "dangling",
+            FileState::Rendered => "rendered",
+            FileState::Stale => "stale",
+            FileState::Copied => "copied",
+            FileState::ModifiedInHome => "modified-in-home",
+            FileState::ModifiedInSyncDir => "modified-in-sync-dir",
+            FileState::ModifiedInBoth => "modified-in-both",
+            FileState::Decrypted => "decrypted",
+        }
+    }
+}
+
+impl fmt::Display for FileState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileState::Linked => write!(f, "correctly linked"),
+            FileState::Missing => write!(f, "missing in home"),
+            FileState::Shadowed => write!(f, "shadowed by a regular file"),
+            FileState::Elsewhere(ref target) => {
+                write!(f, "symlink pointing elsewhere ({})", target.display())
+            }
+            FileState::Dangling(ref target) => write!(f, "dangling ({})", target.display()),
+            FileState::Rendered => write!(f, "rendered from template"),
+            FileState::Stale => write!(f, "outdated rendered template"),
+            FileState::Copied => write!(f, "copied"),
+            FileState::ModifiedInHome => write!(f, "modified in home"),
+            FileState::ModifiedInSyncDir => write!(f, "modified in sync-dir"),
+            FileState::ModifiedInBoth => write!(f, "modified in home and sync-dir"),
+            FileState::Decrypted => write!(f, "decrypted"),
+        }
+    }
+}
+
+/// State of single sync-dir file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStatus {
+    /// file stored in sync-dir
+    pub sync_file: PathBuf,
+    /// home-dir counterpart of `sync_file`
+    pub user_file: PathBuf,
+    /// state of `user_file`
+    pub state: FileState,
+}
+
+/// Returns state of `user_file` which should be a symlink to `sync_file`
+pub fn file_state(sync_file: &Path, user_file: &Path) -> FileState {
+    let metadata = match fs::symlink_metadata(user_file) {
+        Err(_) => return FileState::Missing,
+        Ok(v) => v,
+    };
+    if !metadata.file_type().is_symlink() {
+        return FileState::Shadowed;
+    }
+    let target = fs::read_link(user_file).unwrap_or_default();
+    match (fs::canonicalize(user_file), fs::canonicalize(sync_file)) {
+        (Err(_), _) => FileState::Dangling(target),
+        (Ok(ref a), Ok(ref b)) if a == b => FileState::Linked,
+        _ => FileState::Elsewhere(target),
+    }
+}
+
+/// Returns state of `user_file` which should contain template `sync_file` rendered with `vars`
+pub fn template_state(sync_file: &Path, user_file: &Path, vars: &Vars) -> Result<FileState, Error> {
+    let rendered = render_file(sync_file, vars)?;
+    let metadata = match fs::symlink_metadata(user_file) {
+        Err(_) => return Ok(FileState::Missing),
+        Ok(v) => v,
+    };
+    let up_to_date = !metadata.file_type().is_symlink()
+        && fs::read(user_file)
+            .map(|contents| contents == rendered.as_bytes())
+            .unwrap_or(false);
+    Ok(if up_to_date {
+        FileState::Rendered
+    } else {
+        FileState::Stale
+    })
+}
+
+/// Returns state of `user_file` which should be a copy of `sync_file`
+///
+/// `recorded` is hash of contents the copy had when it was the same as `sync_file`
+/// (see: `ff::copies::Copies`). Symlinks are checked as usual (see: `file_state`)
+pub fn copy_state(sync_file: &Path, user_file: &Path, recorded: Option<&str>) -> FileState {
+    match fs::symlink_metadata(user_file) {
+        Err(_) => return FileState::Missing,
+        Ok(ref m) if m.file_type().is_symlink() => return file_state(sync_file, user_file),
+        Ok(_) => {}
+    }
+    let (user_hash, sync_hash) = match (file_hash(user_file), file_hash(sync_file)) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return FileState::Shadowed,
+    };
+    if user_hash == sync_hash {
+        return FileState::Copied;
+    }
+    match recorded {
+        None => FileState::Shadowed,
+        Some(h) if h == sync_hash => FileState::ModifiedInHome,
+        Some(h) if h == user_hash => FileState::ModifiedInSyncDir,
+        Some(_) => FileState::ModifiedInBoth,
+    }
+}
+
+/// Returns state of each file contained in `layers` (merged as `ff::core::apply_layers` does)
+///
+/// Templates are rendered with `options.vars` to tell if their home-dir files are up to date,
+/// copies (see: `Options::copy`) and secrets are compared with hashes recorded when they were
+/// copied (decrypted)
+pub fn status(home: &Home, layers: &[SyncDir], options: &Options) -> Result<Vec<FileStatus>, Error> {
+    let copies = Copies::load(home.copies_file())?;
+    let mut statuses = Vec::new();
+    for (sync_file, layer) in merge_layers(layers, &options.to_ignore)? {
+        let is_dir = match fs::metadata(&sync_file) {
+            Ok(ref m) if m.is_file() || m.is_dir() => m.is_dir(),
+            _ => continue,
+        };
+        let user_file = home_path_for(&sync_file, layer, home)?;
+        let state = if is_dir {
+            file_state(&sync_file, &user_file)
+        } else if is_template(&sync_file) {
+            template_state(&sync_file, &user_file, &options.vars)?
+        } else if is_encrypted(&sync_file) {
+            secret_state(&sync_file, &user_file, copies.get(&user_file))
+        } else if is_copied(&sync_file, layer, &options.copy)? {
+            copy_state(&sync_file, &user_file, copies.get(&user_file))
+        } else {
+            file_state(&sync_file, &user_file)
+        };
+        statuses.push(FileStatus {
+            state,
+            sync_file,
+            user_file,
+        });
+    }
+    Ok(statuses)
+}
+
+/// Returns files (and dirs) whose permissions differ from those recorded in manifests
+/// of `layers` (see: `ff::core::recorded_modes`), missing ones are left out
+pub fn mode_drift(home: &Home, layers: &[SyncDir], options: &Options) -> Result<Vec<ModeDrift>, Error> {
+    let mut modes = Vec::new();
+    for layer in layers {
+        modes.push(Modes::load(layer)?);
+    }
+    let mut drift: Vec<ModeDrift> = Vec::new();
+    for (sync_file, layer) in merge_layers(layers, &options.to_ignore)? {
+        let layer_modes = &modes[layers.iter().position(|l| l.path() == layer.path()).unwrap_or(0)];
+        for (path, recorded) in recorded_modes(&sync_file, layer, home, options, layer_modes)? {
+            match mode_of(&path) {
+                Some(actual) if actual != recorded && !drift.iter().any(|d| d.path == path) => {
+                    drift.push(ModeDrift {
+                        path,
+                        recorded,
+                        actual,
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(drift)
+}
+
+/// Prints `statuses` as a table readable by humans
+pub fn print_table(statuses: &[FileStatus]) {
+    let width = statuses
+        .iter()
+        .map(|s| s.user_file.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("HOME-DIR FILE".len());
+    println!("{:width$}  STATE", "HOME-DIR FILE", width = width);
+    for status in statuses {
+        println!(
+            "{:width$}  {}",
+            status.user_file.display().to_string(),
+            status.state,
+            width = width
+        );
+    }
+}
+
+/// Prints `statuses` in format stable for scripts
+///
+/// Each line is: `<state>\t<home-dir file>\t<sync-dir file>[\t<symlink target>]`,
+/// paths are printed as they are (also when they aren't UTF-8)
+pub fn print_porcelain(statuses: &[FileStatus]) {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for status in statuses {
+        let mut line = status.state.name().as_bytes().to_vec();
+        let mut paths = vec![&status.user_file, &status.sync_file];
+        match status.state {
+            FileState::Elsewhere(ref target) | FileState::Dangling(ref target) => paths.push(target),
+            _ => {}
+        }
+        for path in paths {
+            line.push(b'\t');
+            line.extend_from_slice(path.as_os_str().as_bytes());
+        }
+        line.push(b'\n');
+        let _ = out.write_all(&line);
+    }
+}
+
+/// Prints `statuses` as JSON objects (one per line)
+///
+/// Objects have the same keys as records of other actions (see: `ff::report::Record`),
+/// `result` is state name and `sync_file` and `target` are added
+pub fn print_json(statuses: &[FileStatus]) {
+    for status in statuses {
+        let target = match status.state {
+            FileState::Elsewhere(ref target) | FileState::Dangling(ref target) => {
+                Some(target.to_string_lossy())
+            }
+            _ => None,
+        };
+        println!(
+            "{}",
+            json!({
+                "path": status.user_file.to_string_lossy(),
+                "action": "status",
+                "result": status.state.name(),
+                "error": null,
+                "sync_file": status.sync_file.to_string_lossy(),
+                "target": target,
+            })
+        );
+    }
+}
+
+/// Prints uncommitted changes of sync-dir (see: `ff::git::Git::uncommitted`) after statuses
+///
+/// Table lists them below a header, `porcelain` lines are `uncommitted\t\t<sync-dir file>`
+/// and JSON objects have `result` set to `uncommitted` (and `path` to null)
+pub fn print_uncommitted(changes: &[(String, PathBuf)], porcelain: bool, format: Format) {
+    if changes.is_empty() {
+        return;
+    }
+    if format == Format::Json {
+        for (_, sync_file) in changes {
+            println!(
+                "{}",
+                json!({
+                    "path": null,
+                    "action": "status",
+                    "result": "uncommitted",
+                    "error": null,
+                    "sync_file": sync_file.to_string_lossy(),
+                    "target": null,
+                })
+            );
+        }
+    } else if porcelain {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for (_, sync_file) in changes {
+            let _ = out.write_all(b"uncommitted\t\t");
+            let _ = out.write_all(sync_file.as_os_str().as_bytes());
+            let _ = out.write_all(b"\n");
+        }
+    } else {
+        println!("\nUncommitted changes of sync-dir:");
+        for (code, sync_file) in changes {
+            println!("  {:2} {}", code, sync_file.display());
+        }
+    }
+}
+
+/// Prints warning about permissions differing from recorded ones (see: `mode_drift`)
+///
+/// `porcelain` lines are `mode-drift\t<path>\t<recorded mode>\t<current mode>`
+/// and JSON objects have `result` set to `mode-drift`
+pub fn print_mode_drift(drift: &[ModeDrift], porcelain: bool, format: Format) {
+    if drift.is_empty() {
+        return;
+    }
+    if format == Format::Json {
+        for item in drift {
+            println!(
+                "{}",
+                json!({
+                    "path": item.path.to_string_lossy(),
+                    "action": "status",
+                    "result": "mode-drift",
+                    "error": null,
+                    "recorded_mode": format!("{:04o}", item.recorded),
+                    "mode": format!("{:04o}", item.actual),
+                })
+            );
+        }
+    } else if porcelain {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for item in drift {
+            let _ = out.write_all(b"mode-drift\t");
+            let _ = out.write_all(item.path.as_os_str().as_bytes());
+            let _ = out.write_all(format!("\t{:04o}\t{:04o}\n", item.recorded, item.actual).as_bytes());
+        }
+    } else {
+        println!("\nWARNING: permissions differ from recorded ones ('ff apply' restores them):");
+        for item in drift {
+            println!("  {:04o} (recorded: {:04o}) {}", item.actual, item.recorded, item.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::fs as unix_fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn status_classifies_each_file() {
+        let homedir = TempDir::new("user1").unwrap();
+        let sync_dir = TempDir::new_in(homedir.path(), "dot-files").unwrap();
+        for name in &["linked", "relative", "missing", "shadowed", "elsewhere", "dangling"] {
+            File::create(sync_dir.path().join(name)).unwrap();
+        }
+        let other_file = homedir.path().join("other");
+        File::create(&other_file).unwrap();
+        unix_fs::symlink(sync_dir.path().join("linked"), homedir.path().join("linked")).unwrap();
+        let relative_target = Path::new(sync_dir.path().file_name().unwrap()).join("relative");
+        unix_fs::symlink(&relative_target, homedir.path().join("relative")).unwrap();
+        File::create(homedir.path().join("shadowed")).unwrap();
+        unix_fs::symlink(&other_file, homedir.path().join("elsewhere")).unwrap();
+        let gone = homedir.path().join("gone");
+        unix_fs::symlink(&gone, homedir.path().join("dangling")).unwrap();
+
+        let mut result = status(
+            &Home::new(homedir.path()),
+            &[SyncDir::new(sync_dir.path())],
+            &Options::default(),
+        ).unwrap();
+        result.sort_by(|a, b| a.sync_file.cmp(&b.sync_file));
+
+        let states = result.into_iter().map(|s| s.state).collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![
+                FileState::Dangling(gone),
+                FileState::Elsewhere(other_file),
+                FileState::Linked,
+                FileState::Missing,
+                FileState::Linked,
+                FileState::Shadowed,
+            ]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! renders `.tmpl` files of sync-dir, so they can differ between machines
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CStr;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use libc;
+
+use config::Config;
+use error::Error;
+
+/// Suffix of sync-dir files which are rendered into home-dir (without the suffix)
+pub const TEMPLATE_SUFFIX: &str = ".tmpl";
+
+/// Returns `true` if `path` is a template (its name ends with `TEMPLATE_SUFFIX`)
+pub fn is_template(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.as_bytes().ends_with(TEMPLATE_SUFFIX.as_bytes()))
+        .unwrap_or(false)
+}
+
+/// Returns `path` of template without `TEMPLATE_SUFFIX` (other paths are returned as they are)
+pub fn rendered_path(path: &Path) -> PathBuf {
+    match path.file_name().map(|name| name.as_bytes()) {
+        Some(name) if is_template(path) && name.len() > TEMPLATE_SUFFIX.len() => {
+            path.with_file_name(OsStr::from_bytes(&name[..name.len() - TEMPLATE_SUFFIX.len()]))
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Returns name of the machine
+fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) };
+    if result != 0 {
+        return String::new();
+    }
+    buf[buf.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Variables available in templates
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Vars {
+    values: HashMap<String, String>,
+}
+
+impl Vars {
+    /// Gives no variables
+    pub fn new() -> Vars {
+        Vars::default()
+    }
+
+    /// Gives variables of current machine:
+    ///
+    /// * `hostname` - name of the machine
+    /// * `os` - operating system, e.g. `linux`, `macos`
+    /// * `env.<NAME>` - environment variables, e.g. `env.USER`
+    /// * values of `[vars]` section of `config`, e.g. `email`
+    pub fn from_config(config: &Config) -> Result<Vars, Error> {
+        let mut vars = Vars::new();
+        vars.set("hostname", hostname());
+        vars.set("os", env::consts::OS);
+        for (name, value) in env::vars() {
+            vars.set(format!("env.{}", name), value);
+        }
+        for (name, value) in config.get_vars()? {
+            vars.set(name, value);
+        }
+        Ok(vars)
+    }
+
+    /// Sets variable `name` to `value`
+    pub fn set<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.values.insert(name.into(), value.into());
+    }
+
+    /// Returns value of variable `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|v| v.as_str())
+    }
+}
+
+/// Piece of template
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    Tag(&'a str),
+}
+
+/// Splits `text` into tokens, tags alone in their lines take the whole lines
+fn tokenize<'a>(text: &'a str) -> Result<Vec<Token<'a>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    loop {
+        let start = match (rest.find("{{"), rest.find("{%")) {
+            (None, None) => break,
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+        };
+        let close = if rest[start..].starts_with("{{") { "}}" } else { "%}" };
+        tokens.push(Token::Text(&rest[..start]));
+        let inner = &rest[start + 2..];
+        let end = inner.find(close).ok_or_else(|| {
+            let line = text[..text.len() - rest.len() + start].lines().count().max(1);
+            format!("unclosed '{}' in line {}", &rest[start..start + 2], line)
+        })?;
+        let content = inner[..end].trim();
+        tokens.push(if close == "}}" {
+            Token::Var(content)
+        } else {
+            Token::Tag(content)
+        });
+        rest = &inner[end + close.len()..];
+    }
+    tokens.push(Token::Text(rest));
+
+    let blank = |c| c == ' ' || c == '\t';
+    let alone = (0..tokens.len())
+        .map(|i| match (i.checked_sub(1).map(|j| &tokens[j]), &tokens[i], tokens.get(i + 1)) {
+            (Some(&Token::Text(before)), &Token::Tag(_), Some(&Token::Text(after))) => {
+                let line_start = before.trim_end_matches(blank);
+                let line_end = after.trim_start_matches(blank);
+                (i == 1 && line_start.is_empty() || line_start.ends_with('\n'))
+                    && (line_end.is_empty() || line_end.starts_with('\n'))
+            }
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+    for i in 0..tokens.len() {
+        if let Token::Text(mut text) = tokens[i] {
+            if i > 0 && alone[i - 1] {
+                text = text.trim_start_matches(blank);
+                text = text.get(1..).unwrap_or("");
+            }
+            if i + 1 < tokens.len() && alone[i + 1] {
+                text = text.trim_end_matches(blank);
+            }
+            tokens[i] = Token::Text(text);
+        }
+    }
+    Ok(tokens)
+}
+
+/// Returns value of condition `cond`: `name`, `name == "value"` or `name != "value"`
+///
+/// Undefined variables are treated as empty
+fn eval(cond: &str, vars: &Vars) -> Result<bool, String> {
+    let parse_value = |v: &str| {
+        let v = v.trim();
+        let quoted = v.len() >= 2
+            && ((v.starts_with('"') && v.ends_with('"'))
+                || (v.starts_with('\'') && v.ends_with('\'')));
+        if quoted {
+            Ok(v[1..v.len() - 1].to_owned())
+        } else {
+            Err(format!("value should be quoted: {}", v))
+        }
+    };
+    if let Some(i) = cond.find("==") {
+        let value = vars.get(cond[..i].trim()).unwrap_or("");
+        return Ok(value == parse_value(&cond[i + 2..])?);
+    }
+    if let Some(i) = cond.find("!=") {
+        let value = vars.get(cond[..i].trim()).unwrap_or("");
+        return Ok(value != parse_value(&cond[i + 2..])?);
+    }
+    if cond.is_empty() || cond.contains(char::is_whitespace) {
+        return Err(format!("invalid condition: {:?}", cond));
+    }
+    Ok(!vars.get(cond).unwrap_or("").is_empty())
+}
+
+/// State of single `if` block
+struct Branch {
+    /// whether enclosing block is rendered
+    parent_active: bool,
+    /// whether any branch of the block was chosen already
+    taken: bool,
+    /// whether current branch is rendered
+    active: bool,
+}
+
+/// Renders template `text` with `vars`
+///
+/// * `{{ name }}` is replaced with value of variable `name` (it has to be defined)
+/// * `{% if cond %}`, `{% elif cond %}`, `{% else %}`, `{% endif %}` keep text of the first
+///   branch whose condition is true, `cond` is `name` (defined and not empty),
+///   `name == "value"` or `name != "value"`
+///
+/// Returns reason when the template is invalid
+pub fn render(text: &str, vars: &Vars) -> Result<String, String> {
+    let mut out = String::new();
+    let mut branches: Vec<Branch> = Vec::new();
+    for token in tokenize(text)? {
+        let active = branches.last().map(|b| b.active).unwrap_or(true);
+        match token {
+            Token::Text(text) => {
+                if active {
+                    out.push_str(text);
+                }
+            }
+            Token::Var(name) => {
+                if active {
+                    let value = vars.get(name)
+                        .ok_or_else(|| format!("undefined variable: {}", name))?;
+                    out.push_str(value);
+                }
+            }
+            Token::Tag(tag) => {
+                let (keyword, cond) = match tag.find(char::is_whitespace) {
+                    Some(i) => (&tag[..i], tag[i..].trim()),
+                    None => (tag, ""),
+                };
+                match keyword {
+                    "if" => {
+                        let chosen = active && eval(cond, vars)?;
+                        branches.push(Branch {
+                            parent_active: active,
+                            taken: chosen,
+                            active: chosen,
+                        });
+                    }
+                    "elif" => {
+                        let branch = branches.last_mut().ok_or("'elif' without 'if'")?;
+                        let chosen = branch.parent_active && !branch.taken && eval(cond, vars)?;
+                        branch.active = chosen;
+                        branch.taken |= chosen;
+                    }
+                    "else" => {
+                        let branch = branches.last_mut().ok_or("'else' without 'if'")?;
+                        branch.active = branch.parent_active && !branch.taken;
+                        branch.taken = true;
+                    }
+                    "endif" => {
+                        branches.pop().ok_or("'endif' without 'if'")?;
+                    }
+                    _ => return Err(format!("unknown tag: {:?}", tag)),
+                }
+            }
+        }
+    }
+    if !branches.is_empty() {
+        return Err("missing 'endif'".to_owned());
+    }
+    Ok(out)
+}
+
+/// Renders template stored at `path` with `vars` (see: `render`)
+pub fn render_file(path: &Path, vars: &Vars) -> Result<String, Error> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| Error::io(format!("Can't read template {:?}", path), e))?;
+    render(&text, vars).map_err(|reason| Error::Template {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_substitutes_vars_and_picks_branches() {
+        let mut vars = Vars::new();
+        vars.set("hostname", "work-laptop");
+        vars.set("email", "joe@example.com");
+        let text = "[user]\n\
+                    {% if hostname == \"work-laptop\" %}\n\
+                    \x20   email = {{ email }}\n\
+                    {% elif env.HOME %}\n\
+                    \x20   email = home\n\
+                    {% else %}\n\
+                    \x20   email = none\n\
+                    {% endif %}\n\
+                    [core]{% if os %} {{ os }}{% endif %}\n";
+
+        assert_eq!(
+            render(text, &vars).unwrap(),
+            "[user]\n    email = joe@example.com\n[core]\n"
+        );
+        assert_eq!(
+            render("{% if email %}\n  {% if os %}\nos\n{% endif %}\nmail\n{% endif %}\n", &vars)
+                .unwrap(),
+            "mail\n"
+        );
+        assert_eq!(
+            render("{{ missing }}", &vars).unwrap_err(),
+            "undefined variable: missing"
+        );
+        assert_eq!(
+            render("{% if email %}x", &vars).unwrap_err(),
+            "missing 'endif'"
+        );
+        assert_eq!(rendered_path(Path::new("/a/.gitconfig.tmpl")), Path::new("/a/.gitconfig"));
+    }
+}
@@ -1,3 +1,4 @@
+import json
 import os
 import shutil
 import subprocess as subp
@@ -99,6 +100,40 @@ class TestAll(Setup, unittest.TestCase):
         self.assertTrue(os.path.exists(orig_file))
         self.assertFalse(os.path.islink(orig_file))
 
+    def test_undo_reverts_add(self):
+        os.chdir(HOME_DIR)
+        subp.run([FF_PATH, 'add', '--file-path', '.bashrc', '--sync-subdir', '.'], stdout=STDOUT)
+
+        subp.run([FF_PATH, 'undo'], stdout=STDOUT)
+
+        self.assertFalse(
+            os.path.exists(
+                os.path.join(DOT_FILES_DIR, '.bashrc')
+            )
+        )
+        orig_file = os.path.join(HOME_DIR, '.bashrc')
+        self.assertTrue(os.path.exists(orig_file))
+        self.assertFalse(os.path.islink(orig_file))
+
+
+    def test_remove_prints_json_and_fails_on_regular_file(self):
+        os.chdir(HOME_DIR)
+        subp.run([FF_PATH, 'add', '--file-path', '.bashrc', '--sync-subdir', '.'], stdout=STDOUT)
+
+        result = subp.run(
+            [FF_PATH, 'remove', '--file-path', '.bashrc', '.bash_history',
+             '--keep-going', '--format', 'json'],
+            stdout=subp.PIPE,
+        )
+
+        records = [json.loads(line) for line in result.stdout.decode().splitlines()]
+        self.assertEqual(
+            sorted((r['path'], r['result']) for r in records),
+            [(os.path.join(HOME_DIR, '.bash_history'), 'failed'),
+             (os.path.join(HOME_DIR, '.bashrc'), 'done')],
+        )
+        self.assertNotEqual(result.returncode, 0)
+
 
 class TestApply(Setup, unittest.TestCase):
     def setUp(self):
@@ -129,6 +164,13 @@ class TestApply(Setup, unittest.TestCase):
             )
         )
 
+    def test_apply_dry_run_changes_nothing(self):
+        self.assertTrue(os.path.exists(self.file_symlinked))
+
+        subp.run([FF_PATH, 'apply', '--sync-subdir', '.', '--dry-run'], stdout=STDOUT)
+
+        self.assertFalse(os.path.islink(self.file_symlinked))
+
 
 class TestApplyWithSpace(Setup, unittest.TestCase):
     def setUp(self):